@@ -14,7 +14,8 @@ pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
     AABB::new(min, max)
 }
 
-#[derive(Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Default)]
 pub struct AABB {
     pub min: Vector3<f32>,
     pub max: Vector3<f32>
@@ -23,7 +24,6 @@ pub struct AABB {
 impl AABB {
     pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Self { AABB { min, max } }
 
-    #[allow(dead_code)]
     pub fn hit(&self, ray: &Ray, mut t_min: f32, mut t_max: f32) -> bool {
         for a in 0..3 {
             let inv_d = 1.0 / ray.direction()[a];
@@ -38,4 +38,9 @@ impl AABB {
         }
         true
     }
+
+    pub fn area(&self) -> f32 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
 }
\ No newline at end of file
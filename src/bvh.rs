@@ -0,0 +1,282 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+use nalgebra::Vector3;
+use rand::Rng;
+use crate::ray::Ray;
+use crate::random;
+use crate::hitable::{Hitable, HitableList, HitRecord};
+use crate::aabb;
+use crate::aabb::AABB;
+use crate::distribution::Distribution1D;
+
+const TRAVERSAL_COST: f32 = 0.125;
+const MAX_LEAF_SIZE: usize = 4;
+
+/// A hitable with its bounding box, if it has one.
+type Bounded = (Option<AABB>, Arc<dyn Hitable>);
+
+pub struct BvhNode {
+    left: Arc<dyn Hitable>,
+    right: Arc<dyn Hitable>,
+    /// None for a root that also holds hitables without a bounding box, visited by every ray
+    bbox: Option<AABB>,
+    /// Probability of sampling the left child when the node is a light
+    left_probability: f32,
+    power: f32
+}
+
+impl BvhNode {
+    /// Splits the hitables of `list` into a tree of bounding boxes. Those without a bounding box
+    /// can't be placed in the tree, so they are kept beside it and tested against every ray.
+    pub fn new(list: HitableList, time0: f32, time1: f32) -> Self {
+        let mut objects = Vec::new();
+        let mut unbounded = Vec::new();
+        for hitable in list.into_vec() {
+            match hitable.bounding_box(time0, time1) {
+                Some(bbox) => objects.push((bbox, hitable)),
+                None => unbounded.push((None, hitable))
+            }
+        }
+        let tree = if objects.len() < 2 {
+            let bbox = objects.first().map_or(AABB::default(), |(bbox, _)| *bbox);
+            BvhNode::leaf(objects, bbox)
+        } else {
+            split(objects)
+        };
+        if unbounded.is_empty() {
+            tree
+        } else {
+            let bbox = tree.bbox;
+            BvhNode::node((bbox, Arc::new(tree)), (None, Arc::new(Leaf::new(unbounded))), None)
+        }
+    }
+
+    fn node(left: Bounded, right: Bounded, bbox: Option<AABB>) -> Self {
+        let weights = Distribution1D::new(&sampling_weights(&[&left, &right]));
+        let power = left.1.power() + right.1.power();
+        BvhNode { left: left.1, right: right.1, bbox, left_probability: weights.pmf(0), power }
+    }
+
+    fn leaf(objects: Vec<(AABB, Arc<dyn Hitable>)>, bbox: AABB) -> Self {
+        let leaf = Leaf::new(objects.into_iter().map(|(bbox, hitable)| (Some(bbox), hitable)).collect());
+        BvhNode::node((Some(bbox), Arc::new(leaf)), (None, Arc::new(Leaf::new(Vec::new()))), Some(bbox))
+    }
+}
+
+/// Weights to sample hitables as lights: their power, or when none emits, the area of their bounding box,
+/// so that the faces of a mesh sampled for the light it focuses are chosen by their size.
+fn sampling_weights(hitables: &[&Bounded]) -> Vec<f32> {
+    let power = hitables.iter().map(|(_, hitable)| hitable.power()).collect::<Vec<f32>>();
+    if power.iter().any(|&p| p > 0.0) {
+        power
+    } else {
+        hitables.iter().map(|(bbox, _)| bbox.map_or(0.0, |bbox| bbox.area())).collect()
+    }
+}
+
+/// Hitables kept together at the bottom of the tree, or beside it when they have no bounding box.
+struct Leaf {
+    hitables: Vec<Arc<dyn Hitable>>,
+    weights: Distribution1D
+}
+
+impl Leaf {
+    fn new(hitables: Vec<Bounded>) -> Self {
+        let weights = Distribution1D::new(&sampling_weights(&hitables.iter().collect::<Vec<&Bounded>>()));
+        Leaf { hitables: hitables.into_iter().map(|(_, hitable)| hitable).collect(), weights }
+    }
+}
+
+impl Hitable for Leaf {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut closest_so_far = t_max;
+        let mut hit_anything: Option<HitRecord> = None;
+        for h in self.hitables.iter() {
+            if let Some(hit) = h.hit(ray, t_min, closest_so_far) {
+                closest_so_far = hit.t;
+                hit_anything = Some(hit);
+            }
+        }
+        hit_anything
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> { None }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
+        self.hitables.iter().enumerate().map(|(i, h)| {
+            let weight = self.weights.pmf(i);
+            if weight > 0.0 { weight * h.pdf_value(o, v) } else { 0.0 }
+        }).sum()
+    }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> {
+        match self.hitables.get(self.weights.sample(random::rng().gen::<f32>())) {
+            Some(h) => h.random(o),
+            None => Vector3::new(1.0, 0.0, 0.0)
+        }
+    }
+
+    fn power(&self) -> f32 { self.hitables.iter().map(|h| h.power()).sum() }
+}
+
+fn centroid(bbox: &AABB, axis: usize) -> f32 {
+    0.5 * (bbox.min[axis] + bbox.max[axis])
+}
+
+fn surrounding_box(objects: &[(AABB, Arc<dyn Hitable>)]) -> AABB {
+    objects.iter().skip(1).fold(objects[0].0, |acc, (b, _)| aabb::surrounding_box(&acc, b))
+}
+
+fn build(mut objects: Vec<(AABB, Arc<dyn Hitable>)>) -> Bounded {
+    if objects.len() == 1 {
+        let (bbox, hitable) = objects.pop().unwrap();
+        (Some(bbox), hitable)
+    } else {
+        let node = split(objects);
+        (node.bbox, Arc::new(node))
+    }
+}
+
+/// Splits `objects` (at least two) where the surface area heuristic says it's cheapest,
+/// or keeps them together in a leaf when that is cheaper than splitting.
+fn split(mut objects: Vec<(AABB, Arc<dyn Hitable>)>) -> BvhNode {
+    let n = objects.len();
+    let bbox = surrounding_box(&objects);
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        objects.sort_by(|a, b| centroid(&a.0, axis).partial_cmp(&centroid(&b.0, axis)).unwrap_or(Ordering::Equal));
+        let mut right_areas = vec![0.0; n];
+        let mut acc = objects[n - 1].0;
+        for i in (1..n).rev() {
+            acc = aabb::surrounding_box(&acc, &objects[i].0);
+            right_areas[i] = acc.area();
+        }
+        let mut acc = objects[0].0;
+        for i in 1..n {
+            let cost = acc.area() * i as f32 + right_areas[i] * (n - i) as f32;
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, i));
+            }
+            acc = aabb::surrounding_box(&acc, &objects[i].0);
+        }
+    }
+    let (cost, axis, mid) = best.unwrap();
+    let split_cost = TRAVERSAL_COST + cost / bbox.area();
+    if n <= MAX_LEAF_SIZE && n as f32 <= split_cost {
+        return BvhNode::leaf(objects, bbox)
+    }
+    objects.sort_by(|a, b| centroid(&a.0, axis).partial_cmp(&centroid(&b.0, axis)).unwrap_or(Ordering::Equal));
+    let right = objects.split_off(mid);
+    BvhNode::node(build(objects), build(right), Some(bbox))
+}
+
+impl Hitable for BvhNode {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        if self.bbox.is_none_or(|bbox| bbox.hit(ray, t_min, t_max)) {
+            let hit_left = self.left.hit(ray, t_min, t_max);
+            let t_max = hit_left.as_ref().map_or(t_max, |hit| hit.t);
+            let hit_right = self.right.hit(ray, t_min, t_max);
+            hit_right.or(hit_left)
+        } else {
+            None
+        }
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> { self.bbox }
+
+    /// Density of the children, each weighted by its probability of being sampled. A direction can
+    /// cross several children, like several faces of a mesh, each of which could have been sampled.
+    /// Directions that miss the box are skipped without visiting the children.
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
+        if self.bbox.is_some_and(|bbox| !bbox.hit(&Ray::new(o, v, 0.0), 0.001, f32::MAX)) {
+            return 0.0
+        }
+        let p = self.left_probability;
+        let left = if p > 0.0 { p * self.left.pdf_value(o, v) } else { 0.0 };
+        let right = if p < 1.0 { (1.0 - p) * self.right.pdf_value(o, v) } else { 0.0 };
+        left + right
+    }
+
+    /// Picks a child in proportion to its power, or to the area of its box when nothing emits.
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> {
+        if random::rng().gen::<f32>() < self.left_probability {
+            self.left.random(o)
+        } else {
+            self.right.random(o)
+        }
+    }
+
+    fn power(&self) -> f32 { self.power }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use crate::material::Lambertian;
+    use crate::texture::ConstantTexture;
+
+    /// Uniform numbers in [0, 1) from a linear congruential generator.
+    fn uniform(state: &mut u32) -> f32 {
+        *state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (*state >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn random_vector(state: &mut u32, scale: f32) -> Vector3<f32> {
+        scale * Vector3::new(uniform(state) - 0.5, uniform(state) - 0.5, uniform(state) - 0.5)
+    }
+
+    fn spheres(count: usize) -> HitableList {
+        let mut state = 7;
+        let mut list = HitableList::default();
+        for _ in 0..count {
+            let center = random_vector(&mut state, 20.0);
+            let radius = 0.1 + uniform(&mut state);
+            list.push(Sphere::new(center, radius, Lambertian::new(ConstantTexture::new(0.5, 0.5, 0.5))));
+        }
+        list
+    }
+
+    /// Sphere that pretends to have no bounding box.
+    struct Unbounded(Sphere<Lambertian<ConstantTexture>>);
+
+    impl Hitable for Unbounded {
+        fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> { self.0.hit(ray, t_min, t_max) }
+
+        fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> { None }
+    }
+
+    /// Asserts that `bvh` finds the same closest hits as a linear scan of `list` along random rays,
+    /// and returns how many rays hit something.
+    fn assert_same_hits(bvh: &BvhNode, list: &HitableList) -> usize {
+        let mut state = 3;
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray::new(random_vector(&mut state, 40.0), random_vector(&mut state, 1.0), 0.0);
+            let expected = list.hit(&ray, 0.001, f32::MAX).map(|hit| hit.t);
+            assert_eq!(bvh.hit(&ray, 0.001, f32::MAX).map(|hit| hit.t), expected);
+            hits += expected.is_some() as usize;
+        }
+        hits
+    }
+
+    #[test]
+    fn same_hits_as_linear_scan() {
+        for count in [0, 1, 2, 3, 5] {
+            assert_same_hits(&BvhNode::new(spheres(count), 0.0, 1.0), &spheres(count));
+        }
+        assert!(assert_same_hits(&BvhNode::new(spheres(200), 0.0, 1.0), &spheres(200)) > 100);
+    }
+
+    #[test]
+    fn unbounded_hitables() {
+        let sphere = || Unbounded(Sphere::new(Vector3::zeros(), 100.0, Lambertian::new(ConstantTexture::new(0.5, 0.5, 0.5))));
+        let mut list = spheres(50);
+        list.push(sphere());
+        let mut expected = spheres(50);
+        expected.push(sphere());
+        let bvh = BvhNode::new(list, 0.0, 1.0);
+        assert!(bvh.bounding_box(0.0, 1.0).is_none());
+        assert_eq!(assert_same_hits(&bvh, &expected), 2000);
+    }
+}
@@ -2,9 +2,10 @@ use std::f32;
 use nalgebra::Vector3;
 use rand::Rng;
 use crate::ray::Ray;
+use crate::random;
 
 fn random_in_unit_disk() -> Vector3<f32> {
-    let mut rng = rand::thread_rng();
+    let mut rng = random::rng();
     let unit = Vector3::new(1.0, 1.0, 0.0);
     loop {
         let p = 2.0 * Vector3::new(rng.gen::<f32>(), rng.gen::<f32>(), 0.0) - unit;
@@ -27,6 +28,7 @@ pub struct Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(look_from: Vector3<f32>, look_at: Vector3<f32>, view_up: Vector3<f32>, vertical_fov: f32, aspect: f32, aperture: f32, focus_dist: f32, time0: f32, time1: f32) -> Self {
         let theta = vertical_fov * f32::consts::PI / 180.0;
         let half_height = focus_dist * f32::tan(theta / 2.0);
@@ -45,6 +47,9 @@ impl Camera {
         }
     }
 
+    /// Interval of time over which the shutter is open.
+    pub fn shutter(&self) -> (f32, f32) { (self.time0, self.time1) }
+
     pub fn get_ray(&self, s: f32, t: f32) -> Ray {
         let origin = if self.lens_radius == 0.0 {
             self.origin
@@ -53,7 +58,7 @@ impl Camera {
             let offset = self.u * rd.x + self.v * rd.y;
             self.origin + offset
         };
-        let time = self.time0 + rand::thread_rng().gen::<f32>() * (self.time1 - self.time0);
+        let time = self.time0 + random::rng().gen::<f32>() * (self.time1 - self.time0);
         Ray::new(origin, self.lower_left_corner + s * self.horizontal + t * self.vertical - origin, time)
     }
 }
\ No newline at end of file
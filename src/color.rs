@@ -0,0 +1,6 @@
+use nalgebra::Vector3;
+
+/// Luminance of a linear sRGB color.
+pub fn luminance(c: &Vector3<f32>) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
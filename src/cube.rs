@@ -25,11 +25,17 @@ impl Cube {
 }
 
 impl Hitable for Cube {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        self.sides.hit(&ray, t_min, t_max)
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.sides.hit(ray, t_min, t_max)
     }
 
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
         Some(AABB::new(self.p_min, self.p_max))
     }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 { self.sides.pdf_value(o, v) }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> { self.sides.random(o) }
+
+    fn power(&self) -> f32 { self.sides.power() }
 }
\ No newline at end of file
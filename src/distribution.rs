@@ -0,0 +1,113 @@
+/// Distribution of indices in proportion to weights, sampled by inverting its cumulative distribution.
+pub struct Distribution1D {
+    cdf: Vec<f32>
+}
+
+impl Distribution1D {
+    /// Weights that are all zero, or not finite, give a uniform distribution.
+    pub fn new(weights: &[f32]) -> Self {
+        let total: f32 = weights.iter().sum();
+        let uniform = !(total > 0.0 && total.is_finite());
+        let mut sum = 0.0;
+        let cdf = weights.iter().enumerate().map(|(i, weight)| {
+            sum += if uniform { 1.0 } else { *weight };
+            if uniform { (i + 1) as f32 / weights.len() as f32 } else { sum / total }
+        }).collect();
+        Distribution1D { cdf }
+    }
+
+    pub fn len(&self) -> usize { self.cdf.len() }
+
+    /// Index sampled from `u` uniformly distributed in [0, 1).
+    pub fn sample(&self, u: f32) -> usize {
+        self.cdf.partition_point(|&c| c <= u).min(self.cdf.len().saturating_sub(1))
+    }
+
+    /// Probability of sampling index `i`.
+    pub fn pmf(&self, i: usize) -> f32 {
+        self.cdf[i] - if i > 0 { self.cdf[i - 1] } else { 0.0 }
+    }
+}
+
+/// Distribution of the cells of a grid in proportion to weights: a row is sampled by the total
+/// weight of its cells, then a column from the weights within the row.
+pub struct Distribution2D {
+    marginal: Distribution1D,
+    conditionals: Vec<Distribution1D>
+}
+
+impl Distribution2D {
+    /// Weights are stored row by row, `width` cells per row.
+    pub fn new(weights: &[f32], width: usize) -> Self {
+        let conditionals = weights.chunks(width).map(Distribution1D::new).collect::<Vec<Distribution1D>>();
+        let marginal = Distribution1D::new(&weights.chunks(width).map(|row| row.iter().sum()).collect::<Vec<f32>>());
+        Distribution2D { marginal, conditionals }
+    }
+
+    /// Column and row sampled from `u` and `v` uniformly distributed in [0, 1).
+    pub fn sample(&self, u: f32, v: f32) -> (usize, usize) {
+        let y = self.marginal.sample(v);
+        (self.conditionals[y].sample(u), y)
+    }
+
+    /// Probability of sampling the cell at column `x` and row `y`.
+    pub fn pmf(&self, x: usize, y: usize) -> f32 {
+        self.marginal.pmf(y) * self.conditionals[y].pmf(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pmf_sums_to_one() {
+        let distribution = Distribution1D::new(&[1.0, 0.0, 3.0, 4.0]);
+        let pmf = (0..distribution.len()).map(|i| distribution.pmf(i)).collect::<Vec<f32>>();
+        assert!((pmf.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!((pmf[0] - 0.125).abs() < 1e-6 && pmf[1] == 0.0 && (pmf[3] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn samples_follow_the_weights() {
+        let distribution = Distribution1D::new(&[1.0, 0.0, 3.0, 4.0]);
+        let mut counts = [0; 4];
+        for i in 0..8000 {
+            counts[distribution.sample((i as f32 + 0.5) / 8000.0)] += 1;
+        }
+        assert_eq!(counts, [1000, 0, 3000, 4000]);
+        assert_eq!(distribution.sample(0.0), 0);
+        assert_eq!(distribution.sample(0.999_999_9), 3);
+    }
+
+    #[test]
+    fn grid_pmf_sums_to_one() {
+        let distribution = Distribution2D::new(&[1.0, 3.0, 0.0, 0.0, 2.0, 2.0], 2);
+        let pmf = (0..3).flat_map(|y| (0..2).map(move |x| (x, y)))
+            .map(|(x, y)| distribution.pmf(x, y))
+            .collect::<Vec<f32>>();
+        assert!((pmf.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!((pmf[1] - 0.375).abs() < 1e-6 && pmf[2] == 0.0 && (pmf[4] - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn grid_samples_follow_the_weights() {
+        let distribution = Distribution2D::new(&[1.0, 3.0, 0.0, 0.0, 2.0, 2.0], 2);
+        let mut counts = [[0; 2]; 3];
+        for i in 0..80 {
+            for j in 0..80 {
+                let (x, y) = distribution.sample((i as f32 + 0.5) / 80.0, (j as f32 + 0.5) / 80.0);
+                counts[y][x] += 1;
+            }
+        }
+        assert_eq!(counts, [[800, 2400], [0, 0], [1600, 1600]]);
+    }
+
+    #[test]
+    fn degenerate_weights_are_uniform() {
+        for weights in [[0.0, 0.0], [f32::INFINITY, 1.0], [f32::NAN, 1.0]] {
+            let distribution = Distribution1D::new(&weights);
+            assert_eq!((distribution.pmf(0), distribution.pmf(1)), (0.5, 0.5));
+        }
+    }
+}
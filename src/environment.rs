@@ -0,0 +1,294 @@
+use std::f32;
+use nalgebra::{Unit, UnitQuaternion, Vector3};
+use rand::Rng;
+use crate::image::Framebuffer;
+use crate::distribution::Distribution2D;
+use crate::onb::ONB;
+use crate::pdf;
+use crate::random;
+use crate::color;
+
+/// Light arriving from infinitely far away, seen by the rays that leave the world.
+/// Its directions can be sampled like those of any other light.
+pub trait Environment: Send + Sync {
+    /// Radiance arriving from `direction`, which points away from the scene.
+    fn radiance(&self, direction: &Vector3<f32>) -> Vector3<f32>;
+
+    /// Density over solid angle of the directions drawn by `random`.
+    fn pdf_value(&self, _direction: &Vector3<f32>) -> f32 { 1.0 / (4.0 * f32::consts::PI) }
+
+    fn random(&self) -> Vector3<f32> { pdf::random_unit_vector() }
+
+    /// Luminance of the radiance averaged over all directions.
+    fn luminance(&self) -> f32;
+}
+
+/// Same radiance from all directions.
+pub struct ConstantEnvironment {
+    color: Vector3<f32>
+}
+
+impl ConstantEnvironment {
+    pub fn new(color: Vector3<f32>) -> Self { ConstantEnvironment { color } }
+}
+
+impl Environment for ConstantEnvironment {
+    fn radiance(&self, _direction: &Vector3<f32>) -> Vector3<f32> { self.color }
+
+    fn luminance(&self) -> f32 { color::luminance(&self.color) }
+}
+
+/// Radiance blended linearly with the height of the direction, from `bottom` straight down
+/// to `top` straight up, like the sky of the first book.
+pub struct GradientEnvironment {
+    bottom: Vector3<f32>,
+    top: Vector3<f32>
+}
+
+impl GradientEnvironment {
+    pub fn new(bottom: Vector3<f32>, top: Vector3<f32>) -> Self { GradientEnvironment { bottom, top } }
+}
+
+impl Environment for GradientEnvironment {
+    fn radiance(&self, direction: &Vector3<f32>) -> Vector3<f32> {
+        let t = 0.5 * (direction.normalize().y + 1.0);
+        self.bottom.lerp(&self.top, t)
+    }
+
+    /// Heights are uniformly distributed over the sphere, so the average is halfway.
+    fn luminance(&self) -> f32 { color::luminance(&(0.5 * (self.bottom + self.top))) }
+}
+
+/// High dynamic range image covering all directions in an equirectangular projection:
+/// columns span the azimuth, starting and ending behind `-z`, and rows the polar angle from `+y`.
+/// Pixels are sampled in proportion to their luminance and to the solid angle they cover.
+pub struct EnvironmentMap {
+    image: Framebuffer,
+    scale: f32,
+    /// Turn of the map around the vertical axis.
+    rotation: UnitQuaternion<f32>,
+    distribution: Distribution2D,
+    luminance: f32
+}
+
+impl EnvironmentMap {
+    /// Map whose radiance is scaled by `scale`, turned by `rotation` degrees around `+y`.
+    pub fn new(image: Framebuffer, scale: f32, rotation: f32) -> Self {
+        let (width, height) = (image.width(), image.height());
+        let mut weights = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = (f32::consts::PI * (y as f32 + 0.5) / height as f32).sin();
+            weights.extend((0..width).map(|x| color::luminance(&image.pixel(x, y)).max(0.0) * sin_theta));
+        }
+        // each pixel covers a solid angle of sin(theta) 2 pi^2 / (width height)
+        let luminance = scale * weights.iter().sum::<f32>() * f32::consts::PI / (2.0 * (width * height) as f32);
+        EnvironmentMap {
+            distribution: Distribution2D::new(&weights, width),
+            image,
+            scale,
+            rotation: UnitQuaternion::from_axis_angle(&Vector3::y_axis(), rotation.to_radians()),
+            luminance
+        }
+    }
+
+    /// Pixel seen along `direction`, with the sine of its polar angle.
+    fn pixel(&self, direction: &Vector3<f32>) -> (usize, usize, f32) {
+        let d = self.rotation.inverse_transform_vector(direction).normalize();
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let u = 0.5 + d.x.atan2(-d.z) / (2.0 * f32::consts::PI);
+        let v = theta / f32::consts::PI;
+        let (width, height) = (self.image.width(), self.image.height());
+        let x = ((u * width as f32) as usize).min(width - 1);
+        let y = ((v * height as f32) as usize).min(height - 1);
+        (x, y, theta.sin())
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: &Vector3<f32>) -> Vector3<f32> {
+        let (x, y, _) = self.pixel(direction);
+        self.scale * self.image.pixel(x, y)
+    }
+
+    fn pdf_value(&self, direction: &Vector3<f32>) -> f32 {
+        let (x, y, sin_theta) = self.pixel(direction);
+        if sin_theta <= 0.0 {
+            return 0.0
+        }
+        let cells = (self.image.width() * self.image.height()) as f32;
+        self.distribution.pmf(x, y) * cells / (2.0 * f32::consts::PI.powi(2) * sin_theta)
+    }
+
+    fn random(&self) -> Vector3<f32> {
+        let mut rng = random::rng();
+        let (x, y) = self.distribution.sample(rng.gen::<f32>(), rng.gen::<f32>());
+        let u = (x as f32 + rng.gen::<f32>()) / self.image.width() as f32;
+        let v = (y as f32 + rng.gen::<f32>()) / self.image.height() as f32;
+        let (theta, phi) = (f32::consts::PI * v, 2.0 * f32::consts::PI * (u - 0.5));
+        let d = Vector3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+        self.rotation.transform_vector(&d)
+    }
+
+    fn luminance(&self) -> f32 { self.luminance }
+}
+
+/// Angular radius of the sun, in radians.
+const SUN_RADIUS: f32 = 0.004_654;
+/// Luminance of the sun before it crosses the atmosphere, in the kcd/m² of the sky model.
+const SUN_LUMINANCE: f32 = 2.0e6;
+/// Wavelengths in micrometers standing for the red, green and blue channels.
+const WAVELENGTHS: [f32; 3] = [0.680, 0.550, 0.440];
+
+/// Coefficients of the Perez sky distribution for the luminance and the two chromaticities.
+fn perez_coefficients(turbidity: f32) -> [[f32; 5]; 3] {
+    let t = turbidity;
+    [
+        [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+        [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+        [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529]
+    ]
+}
+
+/// Relative distribution of the sky at polar angle `theta` and angle `gamma` from the sun.
+fn perez(c: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    (1.0 + c[0] * (c[1] / cos_theta).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+/// Linear sRGB from CIE xyY.
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vector3<f32> {
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Vector3::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).map(|c| c.max(0.0))
+}
+
+/// Analytic daylight of Preetham et al., "A Practical Analytic Model for Daylight": the sky from
+/// the Perez distribution fitted for a `turbidity` of haze, and the sun as a small disk dimmed by
+/// Rayleigh and aerosol scattering along its path through the atmosphere. Below the horizon is black.
+pub struct Sky {
+    sun: Unit<Vector3<f32>>,
+    scale: f32,
+    coefficients: [[f32; 5]; 3],
+    /// Luminance and chromaticities at the zenith, divided by their Perez distribution there.
+    zenith: [f32; 3],
+    sun_radiance: Vector3<f32>,
+    cos_sun_radius: f32,
+    /// Probability of sampling the sun rather than the sky.
+    sun_probability: f32,
+    luminance: f32
+}
+
+impl Sky {
+    /// Sky lit by a sun in direction `sun`, with radiance scaled by `scale` from kcd/m².
+    pub fn new(sun: Vector3<f32>, turbidity: f32, scale: f32) -> Result<Self, String> {
+        let sun = Unit::try_new(sun, 1e-6).ok_or_else(|| String::from("sun direction is zero"))?;
+        if sun.y <= 0.0 {
+            return Err(String::from("sun must be above the horizon"))
+        }
+        if !(1.7..=10.0).contains(&turbidity) {
+            return Err(String::from("turbidity must be between 1.7 and 10"))
+        }
+        let t = turbidity;
+        let theta_s = sun.y.acos();
+        let chi = (4.0 / 9.0 - t / 120.0) * (f32::consts::PI - 2.0 * theta_s);
+        let luminance_z = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let polynomial = |m: [[f32; 4]; 3]| {
+            let thetas = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let row = |r: [f32; 4]| r.iter().zip(&thetas).map(|(a, b)| a * b).sum::<f32>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let x_z = polynomial([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886]]);
+        let y_z = polynomial([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688]]);
+        let coefficients = perez_coefficients(t);
+        let at_zenith = |i: usize| perez(&coefficients[i], 1.0, theta_s);
+        let zenith = [luminance_z / at_zenith(0), x_z / at_zenith(1), y_z / at_zenith(2)];
+
+        // relative optical mass of the air crossed by sunlight, of Kasten and Young
+        let mass = 1.0 / (sun.y + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * t - 0.04586;
+        let sun_radiance = Vector3::from_iterator(WAVELENGTHS.iter().map(|&lambda| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+            SUN_LUMINANCE * rayleigh * aerosol
+        })) * scale;
+        let cos_sun_radius = SUN_RADIUS.cos();
+
+        let mut sky = Sky {
+            sun, scale, coefficients, zenith, sun_radiance, cos_sun_radius,
+            sun_probability: 0.0, luminance: 0.0
+        };
+        // the sky is integrated numerically over the upper hemisphere
+        let (rows, columns) = (64, 128);
+        let mut sky_luminance = 0.0;
+        for i in 0..rows {
+            let theta = 0.5 * f32::consts::PI * (i as f32 + 0.5) / rows as f32;
+            for j in 0..columns {
+                let phi = 2.0 * f32::consts::PI * (j as f32 + 0.5) / columns as f32;
+                let d = Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                sky_luminance += color::luminance(&sky.sky_radiance(&d)) * theta.sin();
+            }
+        }
+        // solid angle of a cell is sin(theta) pi^2 / (rows columns), the average is over 4 pi
+        sky_luminance *= f32::consts::PI / (4.0 * (rows * columns) as f32);
+        let sun_solid_angle = 2.0 * f32::consts::PI * (1.0 - cos_sun_radius);
+        let sun_luminance = color::luminance(&sky.sun_radiance) * sun_solid_angle / (4.0 * f32::consts::PI);
+        sky.luminance = sky_luminance + sun_luminance;
+        // the sky keeps some samples even when the sun outshines it
+        sky.sun_probability = if sky.luminance > 0.0 { (sun_luminance / sky.luminance).clamp(0.1, 0.9) } else { 0.5 };
+        Ok(sky)
+    }
+
+    fn sky_radiance(&self, d: &Vector3<f32>) -> Vector3<f32> {
+        let gamma = d.dot(&self.sun).clamp(-1.0, 1.0).acos();
+        let value = |i: usize| self.zenith[i] * perez(&self.coefficients[i], d.y, gamma);
+        self.scale * xyy_to_rgb(value(1), value(2), value(0))
+    }
+}
+
+impl Environment for Sky {
+    fn radiance(&self, direction: &Vector3<f32>) -> Vector3<f32> {
+        let d = direction.normalize();
+        if d.y <= 0.0 {
+            return Vector3::zeros()
+        }
+        let sky = self.sky_radiance(&d);
+        if d.dot(&self.sun) >= self.cos_sun_radius { sky + self.sun_radiance } else { sky }
+    }
+
+    /// Mixes directions in the cone of the sun with directions over the upper hemisphere.
+    fn pdf_value(&self, direction: &Vector3<f32>) -> f32 {
+        let d = direction.normalize();
+        if d.y <= 0.0 {
+            return 0.0
+        }
+        let cone = if d.dot(&self.sun) >= self.cos_sun_radius {
+            1.0 / (2.0 * f32::consts::PI * (1.0 - self.cos_sun_radius))
+        } else {
+            0.0
+        };
+        self.sun_probability * cone + (1.0 - self.sun_probability) / (2.0 * f32::consts::PI)
+    }
+
+    fn random(&self) -> Vector3<f32> {
+        let mut rng = random::rng();
+        if rng.gen::<f32>() < self.sun_probability {
+            let z = 1.0 - rng.gen::<f32>() * (1.0 - self.cos_sun_radius);
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let phi = 2.0 * f32::consts::PI * rng.gen::<f32>();
+            ONB::build_from_w(&self.sun).local(&Vector3::new(r * phi.cos(), r * phi.sin(), z))
+        } else {
+            let d = pdf::random_unit_vector();
+            Vector3::new(d.x, d.y.abs(), d.z)
+        }
+    }
+
+    fn luminance(&self) -> f32 { self.luminance }
+}
@@ -0,0 +1,63 @@
+use std::io;
+use std::io::Write;
+use nalgebra::Vector3;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const PIXEL_TYPE_FLOAT: i32 = 2;
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn ints(values: &[i32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Writes a single part scanline OpenEXR image with uncompressed 32 bit float R, G, B channels.
+pub fn write(writer: &mut impl Write, width: usize, height: usize, pixels: &[Vector3<f32>]) -> io::Result<()> {
+    let mut channels = Vec::new();
+    // channels are listed, and stored in each scanline, in alphabetical order
+    for name in ["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend_from_slice(&ints(&[1, 1]));
+    }
+    channels.push(0);
+    let window = ints(&[0, 0, width as i32 - 1, height as i32 - 1]);
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&[2, 0, 0, 0]);
+    attribute(&mut header, "channels", "chlist", &channels);
+    attribute(&mut header, "compression", "compression", &[0]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+    writer.write_all(&header)?;
+
+    let line_size = 3 * 4 * width;
+    let chunk_size = 8 + line_size;
+    let table_end = header.len() + 8 * height;
+    for y in 0..height {
+        writer.write_all(&((table_end + y * chunk_size) as u64).to_le_bytes())?;
+    }
+    for (y, row) in pixels.chunks(width).enumerate() {
+        writer.write_all(&ints(&[y as i32, line_size as i32]))?;
+        for c in (0..3).rev() {
+            for pixel in row {
+                writer.write_all(&pixel[c].to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use nalgebra::Vector3;
+
+/// Magic number of the raw grid format.
+const RAW_SIGNATURE: [u8; 4] = *b"GRID";
+/// Magic number and version of Mitsuba volume files.
+const VOL_SIGNATURE: [u8; 4] = [b'V', b'O', b'L', 3];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32(data: &[u8], pos: &mut usize) -> io::Result<u32> {
+    let bytes = data.get(*pos..*pos + 4).ok_or_else(|| invalid("truncated grid file"))?;
+    *pos += 4;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_f32(data: &[u8], pos: &mut usize) -> io::Result<f32> {
+    read_u32(data, pos).map(f32::from_bits)
+}
+
+/// Dense grid of densities, with `x` varying fastest, then `y`, then `z`.
+pub struct DensityGrid {
+    size: [usize; 3],
+    densities: Vec<f32>,
+    max: f32
+}
+
+impl DensityGrid {
+    pub fn new(size: [usize; 3], densities: Vec<f32>) -> io::Result<Self> {
+        if size.contains(&0) || densities.len() != size[0] * size[1] * size[2] {
+            return Err(invalid("grid size does not match its data"))
+        }
+        if densities.iter().any(|d| !(d.is_finite() && *d >= 0.0)) {
+            return Err(invalid("grid densities must be finite and not negative"))
+        }
+        let max = densities.iter().copied().fold(0.0, f32::max);
+        Ok(DensityGrid { size, densities, max })
+    }
+
+    /// Reads a `.vol` file as written by Mitsuba, or else the raw format: the `GRID` magic number,
+    /// the size of the grid along `x`, `y` and `z` as 32 bits integers, and the densities as 32 bits
+    /// floats, all little endian.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut data = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut data)?;
+        match data.get(..4) {
+            Some(signature) if signature == RAW_SIGNATURE => read_raw(&data),
+            Some(signature) if signature == VOL_SIGNATURE => read_vol(&data),
+            _ => Err(invalid("unknown grid format, expected a raw grid or a Mitsuba .vol file"))
+        }
+    }
+
+    /// Largest density, which bounds the density anywhere in the grid.
+    pub fn max(&self) -> f32 { self.max }
+
+    fn density_at(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.densities[(z * self.size[1] + y) * self.size[0] + x]
+    }
+
+    /// Density at `p` in [0, 1]³, interpolated between the voxel centers.
+    pub fn density(&self, p: &Vector3<f32>) -> f32 {
+        let mut cell = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let n = self.size[axis];
+            let x = (p[axis] * n as f32 - 0.5).clamp(0.0, (n - 1) as f32);
+            cell[axis] = (x as usize).min(n.saturating_sub(2));
+            fraction[axis] = x - cell[axis] as f32;
+        }
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for axis in 0..3 {
+                let upper = corner >> axis & 1 == 1;
+                index[axis] = (cell[axis] + upper as usize).min(self.size[axis] - 1);
+                weight *= if upper { fraction[axis] } else { 1.0 - fraction[axis] };
+            }
+            if weight > 0.0 {
+                density += weight * self.density_at(index[0], index[1], index[2]);
+            }
+        }
+        density
+    }
+}
+
+fn read_raw(data: &[u8]) -> io::Result<DensityGrid> {
+    let mut pos = 4;
+    let mut size = [0; 3];
+    for n in size.iter_mut() {
+        *n = read_u32(data, &mut pos)? as usize;
+    }
+    let count = size[0].checked_mul(size[1]).and_then(|n| n.checked_mul(size[2]))
+        .ok_or_else(|| invalid("grid is too large"))?;
+    if (data.len() - pos) / 4 < count {
+        return Err(invalid("truncated grid file"))
+    }
+    let densities = (0..count).map(|_| read_f32(data, &mut pos)).collect::<io::Result<Vec<f32>>>()?;
+    DensityGrid::new(size, densities)
+}
+
+/// Mitsuba volumes store float32 data in little endian after a header with the encoding,
+/// the resolution, the number of channels and the bounding box. Several channels are averaged.
+fn read_vol(data: &[u8]) -> io::Result<DensityGrid> {
+    let mut pos = 4;
+    if read_u32(data, &mut pos)? != 1 {
+        return Err(invalid("unsupported .vol encoding, expected float32"))
+    }
+    let mut size = [0; 3];
+    for n in size.iter_mut() {
+        *n = read_u32(data, &mut pos)? as usize;
+    }
+    let channels = read_u32(data, &mut pos)? as usize;
+    if channels == 0 {
+        return Err(invalid(".vol file has no channels"))
+    }
+    // the bounding box is given by the scene instead
+    pos += 24;
+    let count = size[0].checked_mul(size[1]).and_then(|n| n.checked_mul(size[2]))
+        .ok_or_else(|| invalid("grid is too large"))?;
+    if data.len() < pos || (data.len() - pos) / 4 / channels < count {
+        return Err(invalid("truncated grid file"))
+    }
+    let densities = (0..count).map(|_| {
+        let sum = (0..channels).map(|_| read_f32(data, &mut pos)).sum::<io::Result<f32>>()?;
+        Ok(sum / channels as f32)
+    }).collect::<io::Result<Vec<f32>>>()?;
+    DensityGrid::new(size, densities)
+}
@@ -1,6 +1,9 @@
+use std::sync::Arc;
 use nalgebra::Vector3;
+use rand::Rng;
 use rand::seq::SliceRandom;
 use crate::ray::Ray;
+use crate::random;
 use crate::material::Material;
 use crate::aabb;
 use crate::aabb::AABB;
@@ -14,26 +17,43 @@ pub struct HitRecord<'a> {
     pub material: &'a dyn Material
 }
 
-pub trait Hitable: Sync {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+pub trait Hitable: Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB>;
     fn pdf_value(&self, _o: Vector3<f32>, _v: Vector3<f32>) -> f32 { 0.0 }
     fn random(&self, _o: Vector3<f32>) -> Vector3<f32> { Vector3::new(1.0, 0.0, 0.0) }
+    /// Emitted power, up to a constant factor: the area times the luminance of the average emission.
+    fn power(&self) -> f32 { 0.0 }
+}
+
+impl<H: Hitable + ?Sized> Hitable for Arc<H> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> { (**self).hit(ray, t_min, t_max) }
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> { (**self).bounding_box(t0, t1) }
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 { (**self).pdf_value(o, v) }
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> { (**self).random(o) }
+    fn power(&self) -> f32 { (**self).power() }
 }
 
 #[derive(Default)]
 pub struct HitableList {
-    list: Vec<Box<dyn Hitable>>
+    list: Vec<Arc<dyn Hitable>>
 }
 
 impl HitableList {
     pub fn push(&mut self, hitable: impl Hitable + 'static) {
-        self.list.push(Box::new(hitable))
+        self.list.push(Arc::new(hitable))
+    }
+
+    pub fn into_vec(self) -> Vec<Arc<dyn Hitable>> { self.list }
+
+    /// Hitables that emit light.
+    pub fn emitters(&self) -> Vec<Arc<dyn Hitable>> {
+        self.list.iter().filter(|h| h.power() > 0.0).cloned().collect()
     }
 }
 
 impl Hitable for HitableList {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let mut closest_so_far = t_max;
         let mut hit_anything: Option<HitRecord> = None;
         for h in self.list.iter() {
@@ -51,10 +71,7 @@ impl Hitable for HitableList {
                 match first.bounding_box(t0, t1) {
                     Some(bbox) =>
                         self.list.iter().skip(1).try_fold(bbox, |acc, hitable|
-                            match hitable.bounding_box(t0, t1) {
-                                Some(bbox) => Some(aabb::surrounding_box(&acc, &bbox)),
-                                _ => None
-                            }
+                            hitable.bounding_box(t0, t1).map(|bbox| aabb::surrounding_box(&acc, &bbox))
                         ),
                     _ => None
                 },
@@ -62,13 +79,33 @@ impl Hitable for HitableList {
         }
     }
 
+    /// Hitables are sampled in proportion to their power, or uniformly when none emits.
     fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
-        self.list.iter().map(|h| h.pdf_value(o, v)).sum::<f32>() / self.list.len() as f32
+        let power = self.power();
+        if power > 0.0 {
+            self.list.iter().map(|h| (h.power(), h))
+                .filter(|(p, _)| *p > 0.0)
+                .map(|(p, h)| p * h.pdf_value(o, v)).sum::<f32>() / power
+        } else {
+            self.list.iter().map(|h| h.pdf_value(o, v)).sum::<f32>() / self.list.len() as f32
+        }
     }
 
     fn random(&self, o: Vector3<f32>) -> Vector3<f32> {
-        self.list.choose(&mut rand::thread_rng()).unwrap().random(o)
+        let power = self.power();
+        if power > 0.0 {
+            let mut x = random::rng().gen::<f32>() * power;
+            for h in self.list.iter() {
+                x -= h.power();
+                if x < 0.0 {
+                    return h.random(o)
+                }
+            }
+        }
+        self.list.choose(&mut random::rng()).unwrap().random(o)
     }
+
+    fn power(&self) -> f32 { self.list.iter().map(|h| h.power()).sum() }
 }
 
 pub struct FlipNormals<H: Hitable> {
@@ -80,12 +117,18 @@ impl<H: Hitable> FlipNormals<H> {
 }
 
 impl<H: Hitable> Hitable for FlipNormals<H> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        self.hitable.hit(&ray, t_min, t_max).map(|mut hit| {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.hitable.hit(ray, t_min, t_max).map(|mut hit| {
             hit.normal = -hit.normal;
             hit
         })
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> { self.hitable.bounding_box(t0, t1) }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 { self.hitable.pdf_value(o, v) }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> { self.hitable.random(o) }
+
+    fn power(&self) -> f32 { self.hitable.power() }
 }
\ No newline at end of file
@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use nalgebra::Vector3;
+use crate::{png, pfm, rgbe, exr};
+use crate::tonemap::ToneMapper;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Ppm,
+    Png,
+    Pfm,
+    Hdr,
+    Exr
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(Format::Ppm),
+            "png" => Some(Format::Png),
+            "pfm" => Some(Format::Pfm),
+            "hdr" => Some(Format::Hdr),
+            "exr" => Some(Format::Exr),
+            _ => None
+        }
+    }
+}
+
+/// An 8 bit RGB image, stored row by row from the top.
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads the next whitespace separated token of a netpbm header, skipping comments.
+fn header_token(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut token = String::new();
+    let mut byte = [0u8];
+    loop {
+        reader.read_exact(&mut byte)?;
+        match byte[0] {
+            b'#' if token.is_empty() => {
+                let mut comment = Vec::new();
+                reader.read_until(b'\n', &mut comment)?;
+            },
+            b if b.is_ascii_whitespace() => if !token.is_empty() { return Ok(token) },
+            b => token.push(b as char)
+        }
+    }
+}
+
+/// Reads a binary P6 or ASCII P3 ppm, scaling samples to 8 bits.
+fn read_ppm(reader: &mut impl BufRead) -> io::Result<Image> {
+    let magic = header_token(reader)?;
+    let mut number = || header_token(reader)?.parse::<usize>().map_err(|_| invalid("invalid ppm header"));
+    let (width, height, max_value) = (number()?, number()?, number()?);
+    if width == 0 || height == 0 {
+        return Err(invalid("empty ppm image"))
+    }
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid("invalid ppm maximum value"))
+    }
+    let samples = width.checked_mul(height).and_then(|n| n.checked_mul(3))
+        .ok_or_else(|| invalid("ppm image too large"))?;
+    let values = match magic.as_str() {
+        "P6" => {
+            let size = if max_value < 256 { 1 } else { 2 };
+            let mut data = Vec::new();
+            reader.take((size * samples) as u64).read_to_end(&mut data)?;
+            if data.len() < size * samples {
+                return Err(invalid("truncated ppm data"))
+            }
+            if size == 1 {
+                data.iter().map(|&v| v as usize).collect::<Vec<usize>>()
+            } else {
+                data.chunks(2).map(|v| u16::from_be_bytes([v[0], v[1]]) as usize).collect()
+            }
+        },
+        "P3" => {
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            let values = text.split_whitespace().take(samples)
+                .map(|v| v.parse::<usize>().map_err(|_| invalid("invalid ppm sample")))
+                .collect::<io::Result<Vec<usize>>>()?;
+            if values.len() < samples {
+                return Err(invalid("truncated ppm data"))
+            }
+            values
+        },
+        _ => return Err(invalid("unsupported ppm format, expected P3 or P6"))
+    };
+    let pixels = values.iter().map(|&v| (v.min(max_value) * 255 / max_value) as u8).collect();
+    Ok(Image { width, height, pixels })
+}
+
+impl Image {
+    /// Opens a ppm or png image, choosing the format from the file extension.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        match Format::from_path(path) {
+            Some(Format::Ppm) => read_ppm(&mut reader),
+            Some(Format::Png) => {
+                let (width, height, pixels) = png::read(&mut reader)?;
+                Ok(Image { width, height, pixels })
+            },
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported image format, expected .ppm or .png"))
+        }
+    }
+
+    pub fn width(&self) -> usize { self.width }
+
+    pub fn height(&self) -> usize { self.height }
+
+    /// Color of the pixel at column `x` and row `y` from the top.
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = 3 * (y * self.width + x);
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    pub fn write(&self, writer: &mut impl Write, format: Format) -> io::Result<()> {
+        match format {
+            Format::Png => png::write(writer, self.width, self.height, &self.pixels),
+            _ => {
+                write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+                writer.write_all(&self.pixels)
+            }
+        }
+    }
+}
+
+/// Linear radiance for each pixel, stored row by row from the top.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vector3<f32>>
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vector3<f32>>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Framebuffer { width, height, pixels }
+    }
+
+    /// Opens a pfm or hdr image, choosing the format from the file extension.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let (width, height, pixels) = match Format::from_path(path) {
+            Some(Format::Pfm) => pfm::read(&mut reader)?,
+            Some(Format::Hdr) => rgbe::read(&mut reader)?,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported image format, expected .pfm or .hdr"))
+        };
+        if pixels.len() != width * height {
+            return Err(invalid("truncated image data"))
+        }
+        Ok(Framebuffer { width, height, pixels })
+    }
+
+    pub fn width(&self) -> usize { self.width }
+
+    pub fn height(&self) -> usize { self.height }
+
+    /// Radiance of the pixel at column `x` and row `y` from the top.
+    pub fn pixel(&self, x: usize, y: usize) -> Vector3<f32> { self.pixels[y * self.width + x] }
+
+    /// Tone maps the radiance and quantizes it to 8 bit sRGB.
+    pub fn to_image(&self, tone_mapper: &ToneMapper) -> Image {
+        let pixels = self.pixels.iter().flat_map(|c| tone_mapper.encode(c)).collect();
+        Image { width: self.width, height: self.height, pixels }
+    }
+
+    /// Writes high dynamic range formats as they are, and 8 bit formats through `to_image`.
+    pub fn write(&self, writer: &mut impl Write, format: Format, tone_mapper: &ToneMapper) -> io::Result<()> {
+        match format {
+            Format::Pfm => pfm::write(writer, self.width, self.height, &self.pixels),
+            Format::Hdr => rgbe::write(writer, self.width, self.height, &self.pixels),
+            Format::Exr => exr::write(writer, self.width, self.height, &self.pixels),
+            Format::Ppm | Format::Png => self.to_image(tone_mapper).write(writer, format)
+        }
+    }
+
+    /// Saves the image, choosing the format from the file extension.
+    pub fn save(&self, path: &Path, tone_mapper: &ToneMapper) -> io::Result<()> {
+        let format = Format::from_path(path).ok_or_else(||
+            io::Error::new(io::ErrorKind::InvalidInput, "unsupported image format"))?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer, format, tone_mapper)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ppm(data: &[u8]) -> io::Result<Image> { read_ppm(&mut &data[..]) }
+
+    #[test]
+    fn read_ppm_formats() {
+        let ascii = ppm(b"P3\n# a comment\n2 1\n# another\n100\n0 50 100 100 100 100\n").unwrap();
+        assert_eq!((ascii.width(), ascii.height()), (2, 1));
+        assert_eq!((ascii.pixel(0, 0), ascii.pixel(1, 0)), ([0, 127, 255], [255, 255, 255]));
+        let binary = ppm(b"P6 1 2 255\n\x01\x02\x03\x04\x05\x06").unwrap();
+        assert_eq!((binary.pixel(0, 0), binary.pixel(0, 1)), ([1, 2, 3], [4, 5, 6]));
+        let wide = ppm(b"P6 1 1 65535\n\xff\xff\x80\x00\x00\x00").unwrap();
+        assert_eq!(wide.pixel(0, 0), [255, 127, 0]);
+    }
+
+    #[test]
+    fn invalid_ppm() {
+        for data in [&b"P6 0 1 255\n"[..], b"P3 1 0 255\n", b"P6 2 2 255\n\x00\x00\x00", b"P3 1 1 0\n0 0 0",
+                     b"P5 1 1 255\n\x00", b"P3 99999999999 99999999999 255\n"] {
+            assert_eq!(ppm(data).err().map(|err| err.kind()), Some(io::ErrorKind::InvalidData));
+        }
+    }
+}
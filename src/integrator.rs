@@ -0,0 +1,299 @@
+use std::f32;
+use std::str::FromStr;
+use nalgebra::Vector3;
+use rand::Rng;
+use crate::ray::Ray;
+use crate::random;
+use crate::spectrum::{self, WAVELENGTHS};
+use crate::material::ScatterRecord;
+use crate::hitable::HitRecord;
+use crate::pdf::PDF;
+use crate::distribution::Distribution1D;
+use crate::scene::{Scene, LightSample};
+
+/// Estimates the radiance arriving along camera rays.
+pub trait Integrator: Send + Sync {
+    fn radiance(&self, scene: &Scene, ray: &Ray) -> Vector3<f32>;
+}
+
+/// Weighting of the samples of two strategies in multiple importance sampling.
+#[derive(Clone, Copy)]
+pub enum Heuristic {
+    Balance,
+    Power
+}
+
+impl FromStr for Heuristic {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "balance" => Ok(Heuristic::Balance),
+            "power" => Ok(Heuristic::Power),
+            _ => Err(())
+        }
+    }
+}
+
+impl Heuristic {
+    /// Weight of a sample drawn with density `pdf`, when the other strategy has density `other`.
+    fn weight(self, pdf: f32, other: f32) -> f32 {
+        let (pdf, other) = match self {
+            Heuristic::Balance => (pdf, other),
+            Heuristic::Power => (pdf.powi(2), other.powi(2))
+        };
+        if pdf > 0.0 { pdf / (pdf + other) } else { 0.0 }
+    }
+}
+
+/// Wavelengths followed by a path from the first dispersive material it meets: the rays carry the hero,
+/// which chooses the directions, and the companions share them. Each is weighted by the balance heuristic
+/// over which wavelength of the set is the hero, after Wilkie et al., "Hero Wavelength Spectral Sampling".
+struct HeroWavelengths {
+    wavelengths: [f32; WAVELENGTHS],
+    /// Scattering of each wavelength by the dispersive materials of the path
+    values: [f32; WAVELENGTHS],
+    /// Densities of the directions of the path for each wavelength as the hero, over those of the hero,
+    /// without the last direction sampled from a material, which is weighted against the lights
+    pdfs: [f32; WAVELENGTHS],
+    /// Densities of that last direction, over that of the hero
+    last_pdfs: [f32; WAVELENGTHS]
+}
+
+impl HeroWavelengths {
+    fn new() -> Self {
+        let wavelengths = spectrum::sample_wavelengths(random::rng().gen::<f32>());
+        let ones = [1.0; WAVELENGTHS];
+        HeroWavelengths { wavelengths, values: ones, pdfs: ones, last_pdfs: ones }
+    }
+
+    fn hero(&self) -> Option<f32> { Some(self.wavelengths[0]) }
+
+    /// Color of the light carried by the path, with a further `scattering` of each wavelength at its end.
+    fn color(&self, scattering: impl Fn(f32) -> f32) -> Vector3<f32> {
+        let pdf = self.pdfs.iter().sum::<f32>();
+        self.wavelengths.iter().zip(self.values.iter())
+            .map(|(&wavelength, value)| spectrum::wavelength_weight(wavelength) * (value * scattering(wavelength) / pdf))
+            .sum()
+    }
+
+    /// Leaves the vertex reached by the last direction, once the light emitted there is gathered.
+    fn advance(&mut self) {
+        for (pdf, last_pdf) in self.pdfs.iter_mut().zip(self.last_pdfs.iter_mut()) {
+            *pdf *= *last_pdf;
+            *last_pdf = 1.0;
+        }
+    }
+
+    /// Specular scattering, chosen for each wavelength with `probabilities` that are both its value
+    /// and its density.
+    fn scatter_specular(&mut self, probabilities: [f32; WAVELENGTHS]) {
+        for ((value, pdf), probability) in self.values.iter_mut().zip(self.pdfs.iter_mut()).zip(probabilities.iter()) {
+            let ratio = probability / probabilities[0];
+            *value *= ratio;
+            *pdf *= ratio;
+        }
+    }
+
+    /// Scattering with `values` for each wavelength, in a direction whose densities over that of the hero are `pdfs`.
+    fn scatter(&mut self, values: [f32; WAVELENGTHS], pdfs: [f32; WAVELENGTHS]) {
+        for (value, scattering) in self.values.iter_mut().zip(values.iter()) {
+            *value *= scattering;
+        }
+        self.last_pdfs = pdfs;
+    }
+}
+
+/// Color of the wavelengths followed by a path, white while it carries all of them.
+fn path_color(hero: &Option<HeroWavelengths>) -> Vector3<f32> {
+    hero.as_ref().map_or(Vector3::new(1.0, 1.0, 1.0), |hero| hero.color(|_| 1.0))
+}
+
+/// Unidirectional path tracer with next event estimation: at each diffuse bounce, a sample of
+/// the lights, shapes or delta lights, gathers their emission. The path goes on in a direction sampled
+/// from the lights for a `light_sampling` fraction of the bounces and from the material otherwise,
+/// and emission found that way is weighted against the light sample by multiple importance sampling.
+pub struct PathTracer {
+    max_depth: u32,
+    roulette_depth: u32,
+    heuristic: Heuristic,
+    /// Probabilities of following the lights and the material
+    split: Distribution1D
+}
+
+impl PathTracer {
+    /// Paths have at most `max_depth` bounces, and can be ended by russian roulette
+    /// from `roulette_depth` on.
+    pub fn new(max_depth: u32, roulette_depth: u32, heuristic: Heuristic, light_sampling: f32) -> Self {
+        let split = Distribution1D::new(&[light_sampling, 1.0 - light_sampling]);
+        PathTracer { max_depth, roulette_depth, heuristic, split }
+    }
+}
+
+impl PathTracer {
+    /// Directions sampled from the lights and from `pdf` of the material at `origin`, with the split of the path tracer.
+    fn mixture<'a>(&'a self, scene: &'a Scene, origin: Vector3<f32>, pdf: PDF<'a>) -> PDF<'a> {
+        match scene.light_pdf(origin) {
+            Some(lights) => PDF::mixture(vec![lights, pdf], &self.split),
+            None => pdf
+        }
+    }
+
+    /// Scattering of the material from `ray` towards `scattered`, in the color of the path:
+    /// each wavelength followed by the path scatters by its own amount off dispersive materials.
+    fn scattering(&self, ray: &Ray, hit: &HitRecord, scattered: &Ray, hero: &Option<HeroWavelengths>) -> Vector3<f32> {
+        match hero {
+            Some(hero) if hit.material.dispersive() =>
+                hero.color(|wavelength| hit.material.scattering_pdf(&ray.with_wavelength(Some(wavelength)), hit, scattered)),
+            _ => path_color(hero) * hit.material.scattering_pdf(ray, hit, scattered)
+        }
+    }
+
+    /// Weight of the `emitted` radiance found along `ray`, against the chance of sampling it from the lights.
+    /// `scatter_pdf` is the density of the direction of the ray, none for camera rays
+    /// and after specular bounces, which the lights could not have sampled.
+    fn emission_weight(&self, scene: &Scene, ray: &Ray, emitted: &Vector3<f32>, scatter_pdf: Option<f32>) -> f32 {
+        match scatter_pdf {
+            Some(pdf) if *emitted != Vector3::zeros() =>
+                self.heuristic.weight(pdf, scene.light_pdf_value(ray.origin(), ray.direction())),
+            _ => 1.0
+        }
+    }
+
+    /// Light arriving at the hit point from a sample of the lights, and scattered along `ray`
+    /// by the material, to be multiplied by the attenuation of the material. Light found on shapes
+    /// is weighted against the scattered directions, drawn with `pdf`.
+    fn direct_light(&self, scene: &Scene, ray: &Ray, hit: &HitRecord, pdf: &PDF, hero: &Option<HeroWavelengths>) -> Vector3<f32> {
+        match scene.sample_light(hit.p) {
+            Some((LightSample::Area(lights), probability)) => {
+                let to_light = Ray::new(hit.p, lights.generate(), ray.time());
+                let light_pdf = probability * lights.value(to_light.direction());
+                if light_pdf <= 0.0 {
+                    return Vector3::zeros()
+                }
+                let emitted = match scene.world.hit(&to_light, 0.001, f32::MAX) {
+                    Some(light_hit) => light_hit.material.emitted(&to_light, &light_hit),
+                    None => scene.background(to_light.direction())
+                };
+                if emitted == Vector3::zeros() {
+                    return Vector3::zeros()
+                }
+                let scattering = self.scattering(ray, hit, &to_light, hero);
+                let tint = hit.material.scattering_tint(ray, hit, &to_light);
+                let weight = self.heuristic.weight(light_pdf, pdf.value(to_light.direction()));
+                emitted.component_mul(&tint).component_mul(&scattering) * (weight / light_pdf)
+            },
+            Some((LightSample::Delta(light), probability)) => {
+                let illumination = light.illuminate(hit.p);
+                let to_light = Ray::new(hit.p, illumination.direction, ray.time());
+                // delta lights can't be found by the material samples, so there is nothing to weigh against
+                if illumination.irradiance == Vector3::zeros()
+                    || scene.world.hit(&to_light, 0.001, illumination.distance * 0.9999).is_some() {
+                    return Vector3::zeros()
+                }
+                let tint = hit.material.scattering_tint(ray, hit, &to_light);
+                let scattering = self.scattering(ray, hit, &to_light, hero);
+                illumination.irradiance.component_mul(&tint).component_mul(&scattering) / probability
+            },
+            None => Vector3::zeros()
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, scene: &Scene, ray: &Ray) -> Vector3<f32> {
+        let mut radiance = Vector3::zeros();
+        // weight of the radiance arriving along the current ray in the pixel
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+        let mut ray = Ray::new(ray.origin(), ray.direction(), ray.time());
+        // density of the direction of the current ray, none after specular bounces
+        let mut scatter_pdf: Option<f32> = None;
+        // wavelengths followed by the path once it meets a dispersive material
+        let mut hero: Option<HeroWavelengths> = None;
+        let mut depth = 0;
+        loop {
+            let hit = match scene.world.hit(&ray, 0.001, f32::MAX) {
+                Some(hit) => hit,
+                None => {
+                    let background = scene.background(ray.direction());
+                    let weight = self.emission_weight(scene, &ray, &background, scatter_pdf);
+                    radiance += weight * throughput.component_mul(&background).component_mul(&path_color(&hero));
+                    break
+                }
+            };
+            let emitted = hit.material.emitted(&ray, &hit);
+            let weight = self.emission_weight(scene, &ray, &emitted, scatter_pdf);
+            radiance += weight * throughput.component_mul(&emitted).component_mul(&path_color(&hero));
+            if let Some(hero) = &mut hero {
+                hero.advance();
+            }
+            if depth >= self.max_depth {
+                break
+            }
+            // paths with a small contribution are ended, the survivors weighted up to stay unbiased
+            let survival = if depth >= self.roulette_depth { throughput.component_mul(&path_color(&hero)).max().min(1.0) } else { 1.0 };
+            if survival < 1.0 {
+                if random::rng().gen::<f32>() >= survival {
+                    break
+                }
+                throughput /= survival;
+            }
+            // the hero wavelength chooses the directions from here on, the others would scatter elsewhere
+            if hero.is_none() && hit.material.dispersive() {
+                let wavelengths = HeroWavelengths::new();
+                ray = ray.with_wavelength(wavelengths.hero());
+                hero = Some(wavelengths);
+            }
+            ray = match hit.material.scatter(&ray, &hit) {
+                Some(ScatterRecord::Specular { specular_ray, attenuation }) => {
+                    throughput.component_mul_assign(&attenuation);
+                    if let Some(hero) = hero.as_mut().filter(|_| hit.material.dispersive()) {
+                        hero.scatter_specular(hero.wavelengths.map(|wavelength|
+                            hit.material.specular_probability(&ray.with_wavelength(Some(wavelength)), &hit, &specular_ray)));
+                    }
+                    scatter_pdf = None;
+                    specular_ray.with_wavelength(ray.wavelength())
+                },
+                Some(ScatterRecord::Scatter { pdf, attenuation }) => {
+                    let mixture = self.mixture(scene, hit.p, pdf);
+                    let direct = self.direct_light(scene, &ray, &hit, &mixture, &hero);
+                    radiance += throughput.component_mul(&attenuation).component_mul(&direct);
+                    let direction = mixture.generate();
+                    if direction == Vector3::zeros() {
+                        break
+                    }
+                    let scattered = Ray::new(hit.p, direction, ray.time());
+                    let pdf_value = mixture.value(scattered.direction());
+                    if pdf_value <= 0.0 {
+                        break
+                    }
+                    let scattering_pdf = match hero.as_mut().filter(|_| hit.material.dispersive()) {
+                        Some(hero) => {
+                            // the values of the wavelengths are kept apart, any of them may carry the path on
+                            let companion = |wavelength: f32| ray.with_wavelength(Some(wavelength));
+                            let values = hero.wavelengths.map(|wavelength|
+                                hit.material.scattering_pdf(&companion(wavelength), &hit, &scattered));
+                            let pdfs = hero.wavelengths.map(|wavelength| match hit.material.scatter(&companion(wavelength), &hit) {
+                                Some(ScatterRecord::Scatter { pdf, .. }) => self.mixture(scene, hit.p, pdf).value(direction) / pdf_value,
+                                _ => 0.0
+                            });
+                            hero.scatter(values, pdfs);
+                            if values.iter().any(|&value| value > 0.0) { 1.0 } else { 0.0 }
+                        },
+                        None => hit.material.scattering_pdf(&ray, &hit, &scattered)
+                    };
+                    if scattering_pdf <= 0.0 {
+                        break
+                    }
+                    let tint = hit.material.scattering_tint(&ray, &hit, &scattered);
+                    throughput.component_mul_assign(&(attenuation.component_mul(&tint) * scattering_pdf / pdf_value));
+                    scatter_pdf = Some(pdf_value);
+                    scattered.with_wavelength(ray.wavelength())
+                },
+                None => break
+            };
+            depth += 1;
+        }
+        radiance
+    }
+}
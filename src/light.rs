@@ -0,0 +1,136 @@
+use std::f32;
+use nalgebra::{Unit, Vector3};
+use crate::color::luminance;
+
+/// Light arriving at a point from a delta light.
+pub struct Illumination {
+    /// Unit direction towards the light.
+    pub direction: Vector3<f32>,
+    /// Distance to the light, infinite for directional lights.
+    pub distance: f32,
+    /// Irradiance on a surface facing the light.
+    pub irradiance: Vector3<f32>
+}
+
+/// Light emitted from a single point or from a single direction. Rays can't hit such lights,
+/// which are only reached by sampling them when scattering.
+pub enum DeltaLight {
+    Point { position: Vector3<f32>, intensity: Vector3<f32> },
+    /// Cone of light, whose intensity falls off smoothly between `cos_falloff_start` and `cos_cone`.
+    Spot {
+        position: Vector3<f32>,
+        direction: Unit<Vector3<f32>>,
+        intensity: Vector3<f32>,
+        cos_cone: f32,
+        cos_falloff_start: f32
+    },
+    /// Light from infinitely far away travelling along `direction`, like sunlight.
+    Directional { direction: Unit<Vector3<f32>>, irradiance: Vector3<f32> }
+}
+
+fn smoothstep(x: f32, edge0: f32, edge1: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl DeltaLight {
+    pub fn point(position: Vector3<f32>, intensity: Vector3<f32>) -> Self {
+        DeltaLight::Point { position, intensity }
+    }
+
+    /// Spot light at `position` pointed at `target`, lighting up to `cone` degrees off its axis,
+    /// at full intensity up to `falloff_start` degrees, with `0 <= falloff_start <= cone <= 180`.
+    pub fn spot(
+        position: Vector3<f32>, target: Vector3<f32>, intensity: Vector3<f32>, cone: f32, falloff_start: f32
+    ) -> Result<Self, String> {
+        let direction = Unit::try_new(target - position, 1e-6)
+            .ok_or("spot light target must differ from its position")?;
+        if !(0.0..=cone).contains(&falloff_start) || !(falloff_start..=180.0).contains(&cone) {
+            return Err(String::from("spot light angles must satisfy 0 <= falloff <= cone <= 180"))
+        }
+        Ok(DeltaLight::Spot {
+            position,
+            direction,
+            intensity,
+            cos_cone: cone.to_radians().cos(),
+            cos_falloff_start: falloff_start.to_radians().cos()
+        })
+    }
+
+    pub fn directional(direction: Vector3<f32>, irradiance: Vector3<f32>) -> Result<Self, String> {
+        let direction = Unit::try_new(direction, 1e-6).ok_or("directional light direction must not be zero")?;
+        Ok(DeltaLight::Directional { direction, irradiance })
+    }
+
+    pub fn illuminate(&self, p: Vector3<f32>) -> Illumination {
+        match self {
+            DeltaLight::Point { position, intensity } => {
+                let to_light = position - p;
+                let distance = to_light.norm();
+                Illumination { direction: to_light / distance, distance, irradiance: intensity / distance.powi(2) }
+            },
+            DeltaLight::Spot { position, direction, intensity, cos_cone, cos_falloff_start } => {
+                let to_light = position - p;
+                let distance = to_light.norm();
+                let cos_theta = -to_light.dot(direction) / distance;
+                let falloff = if *cos_falloff_start > *cos_cone {
+                    smoothstep(cos_theta, *cos_cone, *cos_falloff_start)
+                } else if cos_theta >= *cos_cone { 1.0 } else { 0.0 };
+                Illumination {
+                    direction: to_light / distance,
+                    distance,
+                    irradiance: falloff * intensity / distance.powi(2)
+                }
+            },
+            DeltaLight::Directional { direction, irradiance } => {
+                Illumination { direction: -direction.into_inner(), distance: f32::MAX, irradiance: *irradiance }
+            }
+        }
+    }
+
+    /// Emitted power, on the scale of `Hitable::power` that leaves out a factor of pi.
+    /// Directional lights are counted over a disk as large as the scene, of radius `scene_radius`.
+    pub fn power(&self, scene_radius: f32) -> f32 {
+        match self {
+            DeltaLight::Point { intensity, .. } => 4.0 * luminance(intensity),
+            DeltaLight::Spot { intensity, cos_cone, cos_falloff_start, .. } =>
+                2.0 * luminance(intensity) * (1.0 - 0.5 * (cos_cone + cos_falloff_start)),
+            DeltaLight::Directional { irradiance, .. } => scene_radius.powi(2) * luminance(irradiance)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spot(cone: f32, falloff_start: f32) -> Result<DeltaLight, String> {
+        DeltaLight::spot(Vector3::new(0.0, 1.0, 0.0), Vector3::zeros(), Vector3::new(1.0, 1.0, 1.0), cone, falloff_start)
+    }
+
+    #[test]
+    fn spot_angles_are_checked() {
+        assert!(spot(30.0, 20.0).is_ok());
+        assert!(spot(180.0, 0.0).is_ok());
+        assert!(spot(30.0, 40.0).is_err());
+        assert!(spot(190.0, 20.0).is_err());
+        assert!(spot(30.0, -1.0).is_err());
+        assert!(spot(f32::NAN, 20.0).is_err());
+        assert!(spot(30.0, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn spot_falls_off_outside_its_cone() {
+        let light = spot(30.0, 20.0).unwrap();
+        assert_eq!(light.illuminate(Vector3::zeros()).irradiance, Vector3::new(1.0, 1.0, 1.0));
+        assert_eq!(light.illuminate(Vector3::new(1.0, 0.0, 0.0)).irradiance, Vector3::zeros());
+    }
+
+    #[test]
+    fn directions_must_not_be_zero() {
+        assert!(DeltaLight::spot(Vector3::zeros(), Vector3::zeros(), Vector3::zeros(), 30.0, 20.0).is_err());
+        assert!(DeltaLight::directional(Vector3::zeros(), Vector3::zeros()).is_err());
+        let light = DeltaLight::directional(Vector3::new(0.0, -2.0, 0.0), Vector3::new(1.0, 1.0, 1.0)).unwrap();
+        assert_eq!(light.illuminate(Vector3::zeros()).direction, Vector3::new(0.0, 1.0, 0.0));
+    }
+}
@@ -1,34 +1,63 @@
 mod ray;
 mod hitable;
+mod perlin;
 mod texture;
 mod material;
 mod sphere;
 mod rect;
 mod cube;
+mod triangle;
+mod obj;
 mod translate;
 mod rotate;
+mod motion;
+mod medium;
+mod grid;
 mod camera;
 mod aabb;
 mod onb;
 mod pdf;
+mod bvh;
+mod random;
+mod settings;
+mod zlib;
+mod png;
+mod pfm;
+mod rgbe;
+mod exr;
+mod color;
+mod tonemap;
+mod image;
+mod scene;
+mod integrator;
+mod distribution;
+mod light;
+mod environment;
+mod microfacet;
+mod spectrum;
 
-use std::f32;
+use std::{env, f32, io, process};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
 use nalgebra::Vector3;
 use rand::Rng;
 use rayon::prelude::*;
-use crate::ray::Ray;
 use crate::texture::ConstantTexture;
-use crate::material::{ScatterRecord, Lambertian, Metal, Dielectric, DiffuseLight};
-use crate::hitable::{Hitable, HitableList, FlipNormals};
+use crate::material::{Lambertian, Metal, Dielectric, DiffuseLight};
+use crate::hitable::{HitableList, FlipNormals};
 use crate::sphere::Sphere;
 use crate::rect::{AARect, Plane};
 use crate::cube::Cube;
 use crate::translate::Translate;
 use crate::rotate::{Rotate, Axis};
 use crate::camera::Camera;
-use crate::pdf::PDF;
+use crate::settings::Settings;
+use crate::image::{Framebuffer, Format};
+use crate::scene::{Scene, Light};
+use crate::integrator::{Integrator, PathTracer};
 
-fn cornell_box(aspect: f32) -> (Box<dyn Hitable>, Box<dyn Hitable>, Camera) {
+fn cornell_box(aspect: f32) -> Scene {
     let red = Lambertian::new(ConstantTexture::new(0.65, 0.05, 0.05));
     let white = Lambertian::new(ConstantTexture::new(0.73, 0.73, 0.73));
     let green = Lambertian::new(ConstantTexture::new(0.12, 0.45, 0.15));
@@ -36,11 +65,11 @@ fn cornell_box(aspect: f32) -> (Box<dyn Hitable>, Box<dyn Hitable>, Camera) {
     let glass = Dielectric::new(1.5);
     let aluminum = Metal::new(Vector3::new(0.8, 0.85, 0.88), 0.0);
     let light_shape = AARect::new(Plane::ZX, 227.0, 332.0, 213.0, 343.0, 554.0, light);
-    let glass_sphere = Sphere::new(Vector3::new(190.0, 90.0, 190.0), 90.0, glass);
+    let glass_sphere = Arc::new(Sphere::new(Vector3::new(190.0, 90.0, 190.0), 90.0, glass));
     let mut world = HitableList::default();
     world.push(FlipNormals::new(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 555.0, green)));
     world.push(AARect::new(Plane::YZ, 0.0, 555.0, 0.0, 555.0, 0.0, red));
-    world.push(FlipNormals::new(light_shape.clone()));
+    world.push(FlipNormals::new(light_shape));
     world.push(FlipNormals::new(AARect::new(Plane::ZX, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone())));
     world.push(AARect::new(Plane::ZX, 0.0, 555.0, 0.0, 555.0, 0.0, white.clone()));
     world.push(FlipNormals::new(AARect::new(Plane::XY, 0.0, 555.0, 0.0, 555.0, 555.0, white.clone())));
@@ -52,10 +81,6 @@ fn cornell_box(aspect: f32) -> (Box<dyn Hitable>, Box<dyn Hitable>, Camera) {
                         15.0),
             Vector3::new(265.0, 0.0, 295.0)));
 
-    let mut light_shapes = HitableList::default();
-    light_shapes.push(light_shape);
-    light_shapes.push(glass_sphere);
-
     let look_from = Vector3::new(278.0, 278.0, -800.0);
     let look_at = Vector3::new(278.0, 278.0, 0.0);
     let focus_dist = 10.0;
@@ -65,60 +90,79 @@ fn cornell_box(aspect: f32) -> (Box<dyn Hitable>, Box<dyn Hitable>, Camera) {
         look_from, look_at, Vector3::new(0.0, 1.0, 0.0),
         vertical_fov, aspect, aperture, focus_dist, 0.0, 1.0);
 
-    (Box::new(world), Box::new(light_shapes), cam)
+    // the glass sphere is sampled when scattering to find the caustic it focuses
+    Scene::new(world, vec![Light::new(glass_sphere)], Vec::new(), None, cam)
 }
 
-fn color(ray: &Ray, world: &Box<dyn Hitable>, light_shape: &Box<dyn Hitable>, depth: i32) -> Vector3<f32> {
-    if let Some(hit) = world.hit(ray, 0.001, f32::MAX) {
-        let emitted = hit.material.emitted(&ray, &hit);
-        if depth < 50 {
-            if let Some(scatter) = hit.material.scatter(&ray, &hit) {
-                match scatter {
-                    ScatterRecord::Specular { specular_ray, attenuation } => {
-                        return attenuation.zip_map(
-                            &color(&specular_ray, &world, &light_shape, depth+1), |l, r| l * r)
-                    }
-                    ScatterRecord::Scatter { pdf, attenuation } => {
-                        let hitable_pdf = PDF::hitable(&light_shape, hit.p);
-                        let pdf_fun = PDF::mixture(&hitable_pdf, &pdf);
-                        let scattered = Ray::new(hit.p, pdf_fun.generate(), ray.time());
-                        let pdf_val = pdf_fun.value(scattered.direction());
-                        let scattering_pdf = hit.material.scattering_pdf(&ray, &hit, &scattered);
-                        return emitted + attenuation.zip_map(
-                            &(scattering_pdf * color(&scattered, &world, &light_shape, depth+1)), |l, r| l * r) / pdf_val
-                    }
-                }
-            }
-        }
-        emitted
-    } else {
-        Vector3::zeros()
+/// Renders the average of `samples` rays per pixel, by rows from the top.
+fn render(scene: &Scene, integrator: &dyn Integrator, nx: usize, ny: usize, samples: usize, seed: u64) -> Vec<Vector3<f32>> {
+    (0..ny).into_par_iter().rev()
+        .flat_map(|y|
+            (0..nx).map(|x| {
+                random::seed(seed.wrapping_add((y * nx + x) as u64));
+                let col: Vector3<f32> = (0..samples).map(|_| {
+                    let mut rng = random::rng();
+                    let u = (x as f32 + rng.gen::<f32>()) / nx as f32;
+                    let v = (y as f32 + rng.gen::<f32>()) / ny as f32;
+                    let ray = scene.camera.get_ray(u, v);
+                    integrator.radiance(scene, &ray)
+                }).sum();
+                col / samples as f32
+            }).collect::<Vec<Vector3<f32>>>()
+        ).collect::<Vec<Vector3<f32>>>()
+}
+
+/// Returns a built-in scene, or loads a scene file.
+fn scene(name: &str, aspect: f32) -> Result<Scene, String> {
+    match name {
+        "cornell_box" => Ok(cornell_box(aspect)),
+        _ if Path::new(name).is_file() => scene::load(Path::new(name), aspect),
+        _ => Err(format!("unknown scene '{}', available scenes: cornell_box, or a scene file", name))
     }
 }
 
 fn main() {
-    let nx = 500;
-    let ny = 500;
-    let ns = 1000;
-    println!("P3\n{} {}\n255", nx, ny);
-    let (world, light_shape, cam) = cornell_box(nx as f32 / ny as f32);
-    let image =
-        (0..ny).into_par_iter().rev()
-            .flat_map(|y|
-                (0..nx).flat_map(|x| {
-                    let col: Vector3<f32> = (0..ns).map(|_| {
-                        let mut rng = rand::thread_rng();
-                        let u = (x as f32 + rng.gen::<f32>()) / nx as f32;
-                        let v = (y as f32 + rng.gen::<f32>()) / ny as f32;
-                        let ray = cam.get_ray(u, v);
-                        color(&ray, &world, &light_shape, 0)
-                    }).sum();
-                    col.iter().map(|c|
-                        (255.99 * (c / ns as f32).sqrt().max(0.0).min(1.0)) as u8
-                    ).collect::<Vec<u8>>()
-                }).collect::<Vec<u8>>()
-            ).collect::<Vec<u8>>();
-    for col in image.chunks(3) {
-       println!("{} {} {}", col[0], col[1], col[2]);
+    let settings = match Settings::from_args(env::args().skip(1)) {
+        Ok(Some(settings)) => settings,
+        Ok(None) => {
+            println!("{}", settings::USAGE);
+            process::exit(0)
+        },
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, settings::USAGE);
+            process::exit(2)
+        }
+    };
+    if let Some(threads) = settings.threads {
+        rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
+    }
+    let nx = settings.width;
+    let ny = settings.height;
+    let ns = settings.samples;
+    if let Some(path) = &settings.output {
+        if Format::from_path(path).is_none() {
+            eprintln!("error: unsupported output format '{}', use .ppm, .png, .pfm, .hdr or .exr", path.display());
+            process::exit(2)
+        }
+    }
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    let scene = scene(&settings.scene, nx as f32 / ny as f32).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        process::exit(2)
+    });
+    let integrator = PathTracer::new(settings.max_depth, settings.roulette_depth, settings.heuristic,
+                                     settings.light_sampling);
+    let pixels = render(&scene, &integrator, nx, ny, ns, seed);
+    let framebuffer = Framebuffer::new(nx, ny, pixels);
+    let result = match &settings.output {
+        Some(path) => framebuffer.save(path, &settings.tone_mapper),
+        None => {
+            let mut stdout = io::stdout().lock();
+            framebuffer.write(&mut stdout, Format::Ppm, &settings.tone_mapper).and_then(|_| stdout.flush())
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("error: cannot write image: {}", err);
+        process::exit(1)
     }
 }
@@ -1,13 +1,18 @@
 use std::f32;
+use std::sync::Arc;
 use nalgebra::Vector3;
 use rand::Rng;
 use crate::ray::Ray;
+use crate::random;
 use crate::hitable::HitRecord;
 use crate::texture::Texture;
 use crate::pdf::PDF;
+use crate::onb::ONB;
+use crate::microfacet::{self, Ggx, RoughInterface};
+use crate::spectrum;
 
 fn random_in_unit_sphere() -> Vector3<f32> {
-    let mut rng = rand::thread_rng();
+    let mut rng = random::rng();
     let unit = Vector3::new(1.0, 1.0, 1.0);
     loop {
         let p = 2.0 * Vector3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) - unit;
@@ -18,13 +23,13 @@ fn random_in_unit_sphere() -> Vector3<f32> {
 }
 
 fn reflect(v: &Vector3<f32>, n: &Vector3<f32>) -> Vector3<f32> {
-    v - 2.0 * v.dot(&n) * n
+    v - 2.0 * v.dot(n) * n
 }
 
 
 fn refract(v: &Vector3<f32>, n: &Vector3<f32>, ni_over_nt: f32) -> Option<Vector3<f32>> {
     let uv = v.normalize();
-    let dt = uv.dot(&n);
+    let dt = uv.dot(n);
     let discriminant = 1.0 - ni_over_nt.powi(2) * (1.0 - dt.powi(2));
     if discriminant > 0.0 {
         let refracted = ni_over_nt * (uv - n * dt) - n * discriminant.sqrt();
@@ -39,17 +44,79 @@ fn schlick(cosine: f32, ref_idx: f32) -> f32 {
     r0 + (1.0 -r0) * (1.0 - cosine).powi(5)
 }
 
+/// Probability that a specular scattering, reflecting to `reflected` with probability `reflectance`
+/// and refracting to `refracted` otherwise, went along `direction`.
+fn specular_probability(direction: &Vector3<f32>, reflected: &Vector3<f32>, refracted: Option<Vector3<f32>>, reflectance: f32) -> f32 {
+    let along = |d: &Vector3<f32>| (direction.normalize() - d.normalize()).norm() < 1e-4;
+    if along(reflected) {
+        reflectance
+    } else if refracted.is_some_and(|r| along(&r)) {
+        1.0 - reflectance
+    } else {
+        0.0
+    }
+}
+
+/// Fraction of the light left by Beer-Lambert absorption after travelling inside a medium up to the hit,
+/// if the ray is leaving it. The ray must start on the surface of the medium, as it does after entering it.
+fn transmittance(absorption: &Vector3<f32>, ray: &Ray, hit: &HitRecord) -> Vector3<f32> {
+    if ray.direction().dot(&hit.normal) > 0.0 {
+        (-absorption * hit.t * ray.direction().magnitude()).map(f32::exp)
+    } else {
+        Vector3::new(1.0, 1.0, 1.0)
+    }
+}
+
 pub enum ScatterRecord<'a> {
     Specular { specular_ray: Ray, attenuation: Vector3<f32> },
     Scatter { pdf: PDF<'a>, attenuation: Vector3<f32> }
 }
 
-pub trait Material: Sync {
-    fn scatter(&self, _ray: &Ray, _hit: &HitRecord) -> Option<ScatterRecord> { None }
+pub trait Material: Send + Sync {
+    fn scatter(&self, _ray: &Ray, _hit: &HitRecord) -> Option<ScatterRecord<'_>> { None }
 
     fn scattering_pdf(&self, _ray: &Ray, _hit: &HitRecord, _scattered: &Ray) -> f32 { 1.0 }
 
+    /// Color of the light scattered towards `scattered`, on top of the attenuation, for materials
+    /// whose color depends on the directions, such as the Fresnel reflectance of metals.
+    fn scattering_tint(&self, _ray: &Ray, _hit: &HitRecord, _scattered: &Ray) -> Vector3<f32> {
+        Vector3::new(1.0, 1.0, 1.0)
+    }
+
     fn emitted(&self, _ray: &Ray, _hit: &HitRecord) -> Vector3<f32>  { Vector3::zeros() }
+
+    /// Approximate average of the radiance emitted, to balance the sampling of lights.
+    fn emission(&self) -> Vector3<f32> { Vector3::zeros() }
+
+    /// Whether scattering depends on the wavelength of the ray, so that the paths reaching the material
+    /// follow a few wavelengths of the light.
+    fn dispersive(&self) -> bool { false }
+
+    /// Probability that a specular scattering of `ray` goes along `scattered`, for dispersive materials
+    /// to tell how likely the direction chosen for a wavelength was for the others.
+    fn specular_probability(&self, _ray: &Ray, _hit: &HitRecord, _scattered: &Ray) -> f32 { 1.0 }
+}
+
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterRecord<'_>> { (**self).scatter(ray, hit) }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &HitRecord, scattered: &Ray) -> f32 {
+        (**self).scattering_pdf(ray, hit, scattered)
+    }
+
+    fn scattering_tint(&self, ray: &Ray, hit: &HitRecord, scattered: &Ray) -> Vector3<f32> {
+        (**self).scattering_tint(ray, hit, scattered)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &HitRecord) -> Vector3<f32> { (**self).emitted(ray, hit) }
+
+    fn emission(&self) -> Vector3<f32> { (**self).emission() }
+
+    fn dispersive(&self) -> bool { (**self).dispersive() }
+
+    fn specular_probability(&self, ray: &Ray, hit: &HitRecord, scattered: &Ray) -> f32 {
+        (**self).specular_probability(ray, hit, scattered)
+    }
 }
 
 #[derive(Clone)]
@@ -62,7 +129,7 @@ impl<T: Texture> Lambertian<T> {
 }
 
 impl<T: Texture> Material for Lambertian<T> {
-    fn scatter(&self, _ray: &Ray, hit: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(&self, _ray: &Ray, hit: &HitRecord) -> Option<ScatterRecord<'_>> {
         Some(ScatterRecord::Scatter {
             pdf: PDF::cosine(hit.normal),
             attenuation: self.albedo.value(hit.u, hit.v, &hit.p),
@@ -88,7 +155,7 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterRecord<'_>> {
         let mut reflected = reflect(&ray.direction().normalize(), &hit.normal);
         if self.fuzz > 0.0 { reflected += self.fuzz * random_in_unit_sphere() };
         if reflected.dot(&hit.normal) > 0.0 {
@@ -102,40 +169,236 @@ impl Material for Metal {
     }
 }
 
+/// Complex refractive indices `eta` and `k` of common metals, for red, green and blue light.
+pub const METALS: [(&str, [f32; 3], [f32; 3]); 4] = [
+    ("gold", [0.143, 0.374, 1.442], [3.983, 2.386, 1.603]),
+    ("silver", [0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+    ("copper", [0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+    ("aluminum", [1.657, 0.880, 0.521], [9.224, 6.270, 4.837])
+];
+
+/// Metal with a rough surface of GGX microfacets, reflecting light by the Fresnel equations of its
+/// complex refractive index `eta + i k`, per color channel. Anisotropic roughness is given along
+/// `axis` and across it, with the axis projected on the surface.
+#[derive(Clone)]
+pub struct Conductor {
+    eta: Vector3<f32>,
+    k: Vector3<f32>,
+    distribution: Ggx,
+    axis: Vector3<f32>
+}
+
+impl Conductor {
+    pub fn new(eta: Vector3<f32>, k: Vector3<f32>, roughness: f32) -> Self {
+        Conductor::anisotropic(eta, k, roughness, roughness, Vector3::x())
+    }
+
+    pub fn anisotropic(eta: Vector3<f32>, k: Vector3<f32>, roughness_u: f32, roughness_v: f32, axis: Vector3<f32>) -> Self {
+        Conductor { eta, k, distribution: Ggx::from_roughness(roughness_u, roughness_v), axis }
+    }
+
+    fn fresnel(&self, cos_i: f32) -> Vector3<f32> {
+        Vector3::from_fn(|c, _| microfacet::fresnel_conductor(cos_i, self.eta[c], self.k[c]))
+    }
+
+    /// Frame of the distribution at the hit, with the normal turned towards the incoming ray
+    /// so that both sides of a surface reflect.
+    fn frame(&self, ray: &Ray, hit: &HitRecord) -> ONB {
+        let normal = if ray.direction().dot(&hit.normal) > 0.0 { -hit.normal } else { hit.normal };
+        ONB::build_from_wu(&normal, &self.axis)
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterRecord<'_>> {
+        let uvw = self.frame(ray, hit);
+        let wo = -ray.direction().normalize();
+        if self.distribution.is_smooth() {
+            let reflected = reflect(&-wo, &uvw.w());
+            return Some(ScatterRecord::Specular {
+                specular_ray: Ray::new(hit.p, reflected, ray.time()),
+                attenuation: self.fresnel(wo.dot(&uvw.w()))
+            })
+        }
+        Some(ScatterRecord::Scatter {
+            pdf: PDF::microfacet(uvw, wo, self.distribution),
+            attenuation: Vector3::new(1.0, 1.0, 1.0)
+        })
+    }
+
+    /// D G / (4 cos_o), the Fresnel term being the tint.
+    fn scattering_pdf(&self, ray: &Ray, hit: &HitRecord, scattered: &Ray) -> f32 {
+        let uvw = self.frame(ray, hit);
+        let wo = uvw.to_local(&-ray.direction()).normalize();
+        let wi = uvw.to_local(&scattered.direction()).normalize();
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0
+        }
+        let h = (wo + wi).normalize();
+        self.distribution.d(&h) * self.distribution.g2(&wo, &wi) / (4.0 * wo.z)
+    }
+
+    fn scattering_tint(&self, ray: &Ray, _hit: &HitRecord, scattered: &Ray) -> Vector3<f32> {
+        let h = (scattered.direction().normalize() - ray.direction().normalize()).normalize();
+        self.fresnel(scattered.direction().normalize().dot(&h).abs())
+    }
+}
+
+/// Refractive index of a dielectric, constant or varying with the wavelength in micrometers.
+#[derive(Clone, Copy)]
+pub enum Ior {
+    Constant(f32),
+    /// `a + b / wavelength^2`, for glasses in the visible range.
+    Cauchy { a: f32, b: f32 },
+    /// `n^2 = 1 + sum of b wavelength^2 / (wavelength^2 - c)`, the form of glass catalogs.
+    Sellmeier { b: [f32; 3], c: [f32; 3] }
+}
+
+impl Ior {
+    /// Index for light of `wavelength` in nanometers, at the sodium D line for all the visible light.
+    pub fn at(&self, wavelength: Option<f32>) -> f32 {
+        let l2 = (wavelength.unwrap_or(spectrum::D_LINE) / 1000.0).powi(2);
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => (1.0 + b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum::<f32>()).sqrt()
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool { !matches!(self, Ior::Constant(_)) }
+}
+
+impl From<f32> for Ior {
+    fn from(n: f32) -> Self { Ior::Constant(n) }
+}
+
+/// Sellmeier coefficients of common dispersive materials.
+pub const GLASSES: [(&str, Ior); 3] = [
+    ("bk7", Ior::Sellmeier { b: [1.039_612, 0.231_792_34, 1.010_469_5], c: [0.006_000_699, 0.020_017_914, 103.560_65] }),
+    ("fused_silica", Ior::Sellmeier { b: [0.696_166_3, 0.407_942_6, 0.897_479_4], c: [0.004_679_148, 0.013_512_063, 97.934_0] }),
+    ("diamond", Ior::Sellmeier { b: [0.3306, 4.3356, 0.0], c: [0.030_625, 0.011_236, 0.0] })
+];
+
+/// Glass, tinted by absorbing light inside it in proportion to the distance travelled,
+/// and splitting white light into colors when its index depends on the wavelength.
 #[derive(Clone)]
 pub struct Dielectric {
-    ref_idx: f32
+    ior: Ior,
+    absorption: Vector3<f32>
 }
 
 impl Dielectric {
-    pub fn new(ref_idx: f32) -> Self { Dielectric { ref_idx } }
+    pub fn new(ref_idx: f32) -> Self { Dielectric::absorbing(ref_idx.into(), Vector3::zeros()) }
+
+    /// Dielectric absorbing a fraction `1 - exp(-absorption d)` of each color over a distance `d`.
+    pub fn absorbing(ior: Ior, absorption: Vector3<f32>) -> Self { Dielectric { ior, absorption } }
 }
 
-impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterRecord> {
-        let attenuation = Vector3::new(1.0, 1.0, 1.0);
+impl Dielectric {
+    /// Reflection and, unless the light is totally reflected, refraction of `ray`,
+    /// with the probability of the reflection by Schlick's approximation.
+    fn directions(&self, ray: &Ray, hit: &HitRecord) -> (Vector3<f32>, Option<Vector3<f32>>, f32) {
+        let ref_idx = self.ior.at(ray.wavelength());
         let (outward_normal, ni_over_nt, cosine) = if ray.direction().dot(&hit.normal) > 0.0 {
-            let cosine = self.ref_idx * ray.direction().dot(&hit.normal) / ray.direction().magnitude();
-            (-hit.normal, self.ref_idx, cosine)
+            let cosine = ref_idx * ray.direction().dot(&hit.normal) / ray.direction().magnitude();
+            (-hit.normal, ref_idx, cosine)
         } else {
             let cosine = -ray.direction().dot(&hit.normal) / ray.direction().magnitude();
-            (hit.normal, 1.0 / self.ref_idx, cosine)
+            (hit.normal, 1.0 / ref_idx, cosine)
+        };
+        let refracted = refract(&ray.direction(), &outward_normal, ni_over_nt);
+        let reflect_prob = if refracted.is_some() { schlick(cosine, ref_idx) } else { 1.0 };
+        (reflect(&ray.direction(), &hit.normal), refracted, reflect_prob)
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterRecord<'_>> {
+        let attenuation = transmittance(&self.absorption, ray, hit);
+        let (reflected, refracted, reflect_prob) = self.directions(ray, hit);
+        let direction = match refracted {
+            Some(refracted) if random::rng().gen::<f32>() >= reflect_prob => refracted,
+            _ => reflected
         };
-        if let Some(refracted) = refract(&ray.direction(), &outward_normal, ni_over_nt) {
-            let reflect_prob = schlick(cosine, self.ref_idx);
-            if rand::thread_rng().gen::<f32>() >= reflect_prob {
-                return Some(ScatterRecord::Specular {
-                    specular_ray: Ray::new(hit.p, refracted, ray.time()),
-                    attenuation
-                })
-            }
-        }
-        let reflected = reflect(&ray.direction(), &hit.normal);
         Some(ScatterRecord::Specular {
-            specular_ray: Ray::new(hit.p, reflected, ray.time()),
+            specular_ray: Ray::new(hit.p, direction, ray.time()),
+            attenuation
+        })
+    }
+
+    fn dispersive(&self) -> bool { self.ior.is_dispersive() }
+
+    fn specular_probability(&self, ray: &Ray, hit: &HitRecord, scattered: &Ray) -> f32 {
+        let (reflected, refracted, reflect_prob) = self.directions(ray, hit);
+        specular_probability(&scattered.direction(), &reflected, refracted, reflect_prob)
+    }
+}
+
+/// Frosted glass: a dielectric whose surface is made of GGX microfacets of the given roughness,
+/// which reflect and refract light by the exact Fresnel equations.
+/// Like `Dielectric`, the side the normal points to is outside, and light is absorbed inside.
+#[derive(Clone)]
+pub struct RoughDielectric {
+    ior: Ior,
+    distribution: Ggx,
+    absorption: Vector3<f32>
+}
+
+impl RoughDielectric {
+    pub fn new(ior: Ior, roughness: f32, absorption: Vector3<f32>) -> Self {
+        RoughDielectric { ior, distribution: Ggx::from_roughness(roughness, roughness), absorption }
+    }
+
+    fn interface(&self, ray: &Ray) -> RoughInterface {
+        RoughInterface { distribution: self.distribution, eta: self.ior.at(ray.wavelength()) }
+    }
+
+    /// Reflection and refraction through the smooth surface, with the Fresnel reflectance.
+    fn smooth_directions(&self, ray: &Ray, hit: &HitRecord) -> (Vector3<f32>, Option<Vector3<f32>>, f32) {
+        let eta = self.ior.at(ray.wavelength());
+        let direction = ray.direction().normalize();
+        let cos_i = -direction.dot(&hit.normal);
+        let (normal, ni_over_nt) = if cos_i < 0.0 { (-hit.normal, eta) } else { (hit.normal, 1.0 / eta) };
+        let refracted = refract(&direction, &normal, ni_over_nt);
+        let reflectance = if refracted.is_some() { microfacet::fresnel_dielectric(cos_i, eta) } else { 1.0 };
+        (reflect(&direction, &hit.normal), refracted, reflectance)
+    }
+
+    /// Reflection or refraction through the smooth surface, chosen by the Fresnel reflectance.
+    fn scatter_smooth(&self, ray: &Ray, hit: &HitRecord) -> Ray {
+        let (reflected, refracted, reflectance) = self.smooth_directions(ray, hit);
+        let direction = match refracted {
+            Some(refracted) if random::rng().gen::<f32>() >= reflectance => refracted,
+            _ => reflected
+        };
+        Ray::new(hit.p, direction, ray.time())
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterRecord<'_>> {
+        let attenuation = transmittance(&self.absorption, ray, hit);
+        if self.distribution.is_smooth() {
+            return Some(ScatterRecord::Specular { specular_ray: self.scatter_smooth(ray, hit), attenuation })
+        }
+        Some(ScatterRecord::Scatter {
+            pdf: PDF::dielectric(ONB::build_from_w(&hit.normal), -ray.direction(), self.interface(ray)),
             attenuation
         })
     }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &HitRecord, scattered: &Ray) -> f32 {
+        let uvw = ONB::build_from_w(&hit.normal);
+        let wo = uvw.to_local(&-ray.direction()).normalize();
+        self.interface(ray).eval(&wo, &uvw.to_local(&scattered.direction()).normalize())
+    }
+
+    fn dispersive(&self) -> bool { self.ior.is_dispersive() }
+
+    fn specular_probability(&self, ray: &Ray, hit: &HitRecord, scattered: &Ray) -> f32 {
+        let (reflected, refracted, reflectance) = self.smooth_directions(ray, hit);
+        specular_probability(&scattered.direction(), &reflected, refracted, reflectance)
+    }
 }
 
 #[derive(Clone)]
@@ -155,4 +418,58 @@ impl<T: Texture> Material for DiffuseLight<T> {
             Vector3::zeros()
         }
     }
+
+    fn emission(&self) -> Vector3<f32> { self.emit.average() }
+}
+
+/// Phase function scattering the same amount of light in all directions, for participating media.
+#[derive(Clone)]
+pub struct Isotropic<T: Texture> {
+    albedo: T
+}
+
+impl<T: Texture> Isotropic<T> {
+    pub fn new(albedo: T) -> Self { Isotropic { albedo } }
+}
+
+impl<T: Texture> Material for Isotropic<T> {
+    fn scatter(&self, _ray: &Ray, hit: &HitRecord) -> Option<ScatterRecord<'_>> {
+        Some(ScatterRecord::Scatter {
+            pdf: PDF::uniform(),
+            attenuation: self.albedo.value(hit.u, hit.v, &hit.p)
+        })
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, _hit: &HitRecord, _scattered: &Ray) -> f32 {
+        1.0 / (4.0 * f32::consts::PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glass(name: &str) -> Ior {
+        GLASSES.iter().find(|(glass, _)| *glass == name).unwrap().1
+    }
+
+    #[test]
+    fn indices_follow_their_formula() {
+        assert_eq!(Ior::Constant(1.5).at(Some(400.0)), 1.5);
+        assert!((Ior::Cauchy { a: 1.5, b: 0.01 }.at(Some(500.0)) - 1.54).abs() < 1e-6);
+        assert_eq!(Ior::Cauchy { a: 1.5, b: 0.01 }.at(None), Ior::Cauchy { a: 1.5, b: 0.01 }.at(Some(spectrum::D_LINE)));
+        // catalog values at the sodium D line
+        assert!((glass("bk7").at(None) - 1.5168).abs() < 1e-3);
+        assert!((glass("fused_silica").at(None) - 1.4585).abs() < 1e-3);
+        assert!((glass("diamond").at(None) - 2.417).abs() < 5e-3);
+    }
+
+    #[test]
+    fn glasses_disperse_blue_more_than_red() {
+        for (name, ior) in GLASSES.iter() {
+            assert!(ior.is_dispersive());
+            assert!(ior.at(Some(spectrum::MIN_WAVELENGTH)) > ior.at(Some(spectrum::MAX_WAVELENGTH)), "{}", name);
+        }
+        assert!(!Ior::from(1.5).is_dispersive());
+    }
 }
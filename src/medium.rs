@@ -0,0 +1,128 @@
+use std::f32;
+use nalgebra::Vector3;
+use rand::Rng;
+use crate::ray::Ray;
+use crate::random;
+use crate::hitable::{Hitable, HitRecord};
+use crate::material::Material;
+use crate::aabb::AABB;
+use crate::grid::DensityGrid;
+
+/// Participating medium of constant density filling a closed, convex boundary,
+/// such as smoke or fog. Rays go through it until a free flight distance sampled
+/// from the density, where they hit the medium and scatter by its phase function.
+pub struct ConstantMedium<H: Hitable, M: Material> {
+    boundary: H,
+    density: f32,
+    phase_function: M
+}
+
+impl<H: Hitable, M: Material> ConstantMedium<H, M> {
+    /// Fills `boundary` with a medium of the given `density`, which must be positive.
+    pub fn new(boundary: H, density: f32, phase_function: M) -> Result<Self, String> {
+        if density.is_nan() || density <= 0.0 {
+            return Err(String::from("medium density must be positive"))
+        }
+        Ok(ConstantMedium { boundary, density, phase_function })
+    }
+}
+
+impl<H: Hitable, M: Material> Hitable for ConstantMedium<H, M> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        // the ray may start inside the boundary, so look for the entry point behind it too
+        let t_enter = self.boundary.hit(ray, -f32::MAX, f32::MAX)?.t;
+        let t_exit = self.boundary.hit(ray, t_enter + 0.0001, f32::MAX)?.t;
+        let t_enter = t_enter.max(t_min);
+        let t_exit = t_exit.min(t_max);
+        if t_enter >= t_exit {
+            return None
+        }
+        let length = ray.direction().norm();
+        let distance_inside = (t_exit - t_enter) * length;
+        let hit_distance = -(1.0 - random::rng().gen::<f32>()).ln() / self.density;
+        if hit_distance > distance_inside {
+            return None
+        }
+        let t = t_enter + hit_distance / length;
+        Some(HitRecord {
+            t,
+            u: 0.0,
+            v: 0.0,
+            p: ray.point_at_parameter(t),
+            // arbitrary, a phase function does not depend on it
+            normal: Vector3::new(1.0, 0.0, 0.0),
+            material: &self.phase_function
+        })
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> { self.boundary.bounding_box(t0, t1) }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 { self.boundary.pdf_value(o, v) }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> { self.boundary.random(o) }
+}
+
+/// Participating medium whose density varies over a voxel grid stretched over an axis aligned box.
+/// Free flights are sampled by delta tracking: tentative collisions are drawn against the largest
+/// density of the grid, and each one is real with the ratio of the local density to that bound.
+pub struct GridMedium<M: Material> {
+    grid: DensityGrid,
+    bbox: AABB,
+    scale: f32,
+    phase_function: M
+}
+
+impl<M: Material> GridMedium<M> {
+    /// Densities of the grid are multiplied by `scale`.
+    pub fn new(grid: DensityGrid, min: Vector3<f32>, max: Vector3<f32>, scale: f32, phase_function: M) -> Self {
+        GridMedium { grid, bbox: AABB::new(min.inf(&max), min.sup(&max)), scale, phase_function }
+    }
+
+    /// Range of the ray parameter inside the box.
+    fn clip(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let (mut t_enter, mut t_exit) = (t_min, t_max);
+        for a in 0..3 {
+            let inv_d = 1.0 / ray.direction()[a];
+            let t0 = (self.bbox.min[a] - ray.origin()[a]) * inv_d;
+            let t1 = (self.bbox.max[a] - ray.origin()[a]) * inv_d;
+            let (t0, t1) = if inv_d < 0.0 { (t1, t0) } else { (t0, t1) };
+            // NaN when the ray lies in a face, which is outside
+            t_enter = t_enter.max(t0);
+            t_exit = t_exit.min(t1);
+        }
+        if t_enter < t_exit { Some((t_enter, t_exit)) } else { None }
+    }
+}
+
+impl<M: Material> Hitable for GridMedium<M> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let majorant = self.scale * self.grid.max();
+        if majorant <= 0.0 {
+            return None
+        }
+        let (mut t, t_exit) = self.clip(ray, t_min, t_max)?;
+        let length = ray.direction().norm();
+        let size = self.bbox.max - self.bbox.min;
+        let mut rng = random::rng();
+        loop {
+            t -= (1.0 - rng.gen::<f32>()).ln() / (majorant * length);
+            if t >= t_exit {
+                return None
+            }
+            let p = ray.point_at_parameter(t);
+            let local = (p - self.bbox.min).component_div(&size);
+            if rng.gen::<f32>() * majorant < self.scale * self.grid.density(&local) {
+                return Some(HitRecord {
+                    t,
+                    u: 0.0,
+                    v: 0.0,
+                    p,
+                    normal: Vector3::new(1.0, 0.0, 0.0),
+                    material: &self.phase_function
+                })
+            }
+        }
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> { Some(self.bbox) }
+}
@@ -0,0 +1,255 @@
+use std::f32;
+use nalgebra::Vector3;
+
+/// Trowbridge-Reitz (GGX) distribution of microfacet normals, in a local frame where the surface
+/// normal is `z`. Roughness can differ along `x` and `y` for anisotropic surfaces such as brushed metal.
+#[derive(Clone, Copy)]
+pub struct Ggx {
+    alpha_x: f32,
+    alpha_y: f32
+}
+
+/// Smallest alpha along either axis, below which the densities overflow.
+const MIN_ALPHA: f32 = 1e-4;
+
+impl Ggx {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
+        Ggx { alpha_x: alpha_x.max(MIN_ALPHA), alpha_y: alpha_y.max(MIN_ALPHA) }
+    }
+
+    /// Distribution from perceptual roughness in [0, 1], squared as usual to get alpha.
+    pub fn from_roughness(roughness_x: f32, roughness_y: f32) -> Self {
+        Ggx::new(roughness_x.powi(2), roughness_y.powi(2))
+    }
+
+    /// Surfaces this smooth are handled as perfect mirrors, the distribution being too peaked to evaluate.
+    pub fn is_smooth(&self) -> bool { self.alpha_x.max(self.alpha_y) < 1e-3 }
+
+    /// Density of microfacet normals `h` per projected area of the surface.
+    pub fn d(&self, h: &Vector3<f32>) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0
+        }
+        let e = (h.x / self.alpha_x).powi(2) + (h.y / self.alpha_y).powi(2) + h.z.powi(2);
+        1.0 / (f32::consts::PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: &Vector3<f32>) -> f32 {
+        let tan2 = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / w.z.powi(2);
+        0.5 * ((1.0 + tan2).sqrt() - 1.0)
+    }
+
+    /// Fraction of the microfacets facing `w` that are visible from it.
+    pub fn g1(&self, w: &Vector3<f32>) -> f32 { 1.0 / (1.0 + self.lambda(w)) }
+
+    /// Fraction of the microfacets visible from both directions, with correlated heights.
+    pub fn g2(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Microfacet normal sampled in proportion to its area visible from `wo`, with `u1` and `u2`
+    /// uniform in [0, 1), after Heitz, "Sampling the GGX Distribution of Visible Normals".
+    pub fn sample_visible_normal(&self, wo: &Vector3<f32>, u1: f32, u2: f32) -> Vector3<f32> {
+        // stretch the view so the distribution becomes that of a hemisphere
+        let vh = Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+        let length2 = vh.x.powi(2) + vh.y.powi(2);
+        let t1 = if length2 > 0.0 { Vector3::new(-vh.y, vh.x, 0.0) / length2.sqrt() } else { Vector3::x() };
+        let t2 = vh.cross(&t1);
+        // a disk sample, squeezed onto the part of the hemisphere seen from vh
+        let r = u1.sqrt();
+        let phi = 2.0 * f32::consts::PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        Vector3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(0.0)).normalize()
+    }
+
+    /// Density of the normals drawn by `sample_visible_normal`.
+    pub fn visible_normal_pdf(&self, wo: &Vector3<f32>, h: &Vector3<f32>) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z
+    }
+}
+
+/// Fresnel reflectance of a conductor with complex refractive index `eta + i k` relative to the
+/// outside, for light arriving at `cos_i` from the normal.
+pub fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t1 = a2b2 + cos2;
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rs + rp)
+}
+
+/// Fresnel reflectance of an interface with relative refractive index `eta`, the inside over the
+/// outside, for light arriving at `cos_i` from the normal, negative from the inside.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i.min(1.0), eta) };
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+/// Rough boundary between two dielectrics, reflecting and refracting off GGX microfacets,
+/// after Walter et al., "Microfacet Models for Refraction through Rough Surfaces".
+/// Directions are in a local frame whose `z` is the normal towards the outside, and `eta` is
+/// the refractive index of the inside over that of the outside.
+#[derive(Clone, Copy)]
+pub struct RoughInterface {
+    pub distribution: Ggx,
+    pub eta: f32
+}
+
+impl RoughInterface {
+    /// The same interface seen from the inside, where the visible normals are sampled for `wo` below it.
+    fn flipped(&self) -> Self {
+        RoughInterface { distribution: self.distribution, eta: 1.0 / self.eta }
+    }
+
+    /// Microfacet normal between `wo` and `wi`, facing the outside, with the ratio of the indices
+    /// of the sides of `wi` and `wo`, or none if the directions can't be joined by a visible facet.
+    fn half_vector(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> Option<(Vector3<f32>, f32)> {
+        if wo.z == 0.0 || wi.z == 0.0 {
+            return None
+        }
+        let reflection = wo.z * wi.z > 0.0;
+        let eta = if reflection { 1.0 } else if wo.z > 0.0 { self.eta } else { 1.0 / self.eta };
+        let h = (wi * eta + wo).try_normalize(1e-9)?;
+        let h = if h.z < 0.0 { -h } else { h };
+        // facets are seen from the front by both directions
+        if h.dot(wi) * wi.z < 0.0 || h.dot(wo) * wo.z < 0.0 {
+            return None
+        }
+        Some((h, eta))
+    }
+
+    /// Direction scattered from `wo`, reflected with the probability of the Fresnel reflectance
+    /// of a visible normal, refracted otherwise. `u` holds three numbers uniform in [0, 1).
+    /// None when the facet sends the light to the wrong side of the surface, where the density
+    /// belongs to the other kind of scattering.
+    pub fn sample(&self, wo: &Vector3<f32>, u: [f32; 3]) -> Option<Vector3<f32>> {
+        if wo.z < 0.0 {
+            return self.flipped().sample(&-wo, u).map(|wi| -wi)
+        }
+        let h = self.distribution.sample_visible_normal(wo, u[0], u[1]);
+        let cos_o = wo.dot(&h);
+        let reflected = u[2] < fresnel_dielectric(cos_o, self.eta);
+        let wi = if reflected {
+            2.0 * cos_o * h - wo
+        } else {
+            // refraction through the facet, which faces wo
+            let sin2_t = (1.0 - cos_o * cos_o) / (self.eta * self.eta);
+            let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+            -wo / self.eta + (cos_o / self.eta - cos_t) * h
+        };
+        if (wi.z > 0.0) == reflected { Some(wi) } else { None }
+    }
+
+    /// Density of the directions drawn by `sample`.
+    pub fn pdf(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> f32 {
+        if wo.z < 0.0 {
+            return self.flipped().pdf(&-wo, &-wi)
+        }
+        let (h, eta) = match self.half_vector(wo, wi) {
+            Some(half) => half,
+            None => return 0.0
+        };
+        let reflectance = fresnel_dielectric(wo.dot(&h), self.eta);
+        let normal_pdf = self.distribution.visible_normal_pdf(wo, &h);
+        if wo.z * wi.z > 0.0 {
+            reflectance * normal_pdf / (4.0 * wo.dot(&h).abs())
+        } else {
+            let denominator = (wi.dot(&h) + wo.dot(&h) / eta).powi(2);
+            (1.0 - reflectance) * normal_pdf * wi.dot(&h).abs() / denominator
+        }
+    }
+
+    /// Scattering function times the cosine of `wi`, for radiance arriving along `wi` and leaving
+    /// along `wo`. Refraction concentrates radiance by the square of the ratio of the indices.
+    pub fn eval(&self, wo: &Vector3<f32>, wi: &Vector3<f32>) -> f32 {
+        if wo.z < 0.0 {
+            return self.flipped().eval(&-wo, &-wi)
+        }
+        let (h, eta) = match self.half_vector(wo, wi) {
+            Some(half) => half,
+            None => return 0.0
+        };
+        let reflectance = fresnel_dielectric(wo.dot(&h), self.eta);
+        let dg = self.distribution.d(&h) * self.distribution.g2(wo, wi);
+        if wo.z * wi.z > 0.0 {
+            reflectance * dg / (4.0 * wo.z.abs())
+        } else {
+            let denominator = (wi.dot(&h) + wo.dot(&h) / eta).powi(2);
+            (1.0 - reflectance) * dg * (wi.dot(&h) * wo.dot(&h) / (wo.z * denominator)).abs() / (eta * eta)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::random;
+
+    #[test]
+    fn smooth_distributions_stay_finite() {
+        let ggx = Ggx::new(0.0, 0.5);
+        let wo = Vector3::new(0.6, 0.0, 0.8);
+        let h = ggx.sample_visible_normal(&wo, 0.3, 0.7);
+        assert!(ggx.d(&h).is_finite() && ggx.visible_normal_pdf(&wo, &h).is_finite());
+        assert!(ggx.d(&Vector3::z()).is_finite());
+    }
+
+    #[test]
+    fn rough_interface_samples_follow_their_density() {
+        let mut rng = random::rng();
+        for &wo in &[Vector3::new(0.6, 0.0, 0.8), Vector3::new(0.0, 0.6, -0.8)] {
+            let interface = RoughInterface { distribution: Ggx::from_roughness(0.5, 0.5), eta: 1.5 };
+            // the density integrates to at most one, the rest being the samples that are lost
+            let (rows, columns) = (400, 400);
+            let cell = 4.0 * f32::consts::PI / (rows * columns) as f32;
+            let (mut total, mut same_side) = (0.0, 0.0);
+            for i in 0..rows {
+                let z = 1.0 - 2.0 * (i as f32 + 0.5) / rows as f32;
+                let r = (1.0 - z * z).sqrt();
+                for j in 0..columns {
+                    let phi = 2.0 * f32::consts::PI * (j as f32 + 0.5) / columns as f32;
+                    let wi = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+                    let p = interface.pdf(&wo, &wi) * cell;
+                    total += p;
+                    if wi.z * wo.z > 0.0 {
+                        same_side += p;
+                    }
+                }
+            }
+            assert!(total <= 1.01 && total > 0.9, "density integrates to {}", total);
+            let samples = 100_000;
+            let (mut reflected, mut refracted) = (0, 0);
+            for _ in 0..samples {
+                if let Some(wi) = interface.sample(&wo, [rng.gen(), rng.gen(), rng.gen()]) {
+                    assert!(interface.pdf(&wo, &wi.normalize()) > 0.0);
+                    if wi.z * wo.z > 0.0 { reflected += 1 } else { refracted += 1 }
+                }
+            }
+            let (reflected, refracted) = (reflected as f32 / samples as f32, refracted as f32 / samples as f32);
+            assert!((reflected - same_side).abs() < 0.01, "{} of the samples reflected, {} expected", reflected, same_side);
+            assert!((refracted - (total - same_side)).abs() < 0.02,
+                "{} of the samples refracted, {} expected", refracted, total - same_side);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::f32;
+use nalgebra::{Isometry3, Point3, Translation3, UnitQuaternion, Vector3};
+use crate::ray::Ray;
+use crate::hitable::{Hitable, HitRecord};
+use crate::aabb::{self, AABB};
+
+/// Times at which the transform is evaluated to bound the motion, besides the keyframes.
+const BOUNDING_SAMPLES: usize = 32;
+
+/// Placement of an animated shape at a given time.
+#[derive(Clone, Copy)]
+pub struct Keyframe {
+    pub time: f32,
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>
+}
+
+/// Rigid transform of a shape that changes over time, interpolated between keyframes:
+/// linearly for the translation and by slerp for the rotation.
+/// Before the first and after the last keyframe the shape stays still.
+pub struct Animated<H: Hitable> {
+    hitable: H,
+    keyframes: Vec<Keyframe>
+}
+
+impl<H: Hitable> Animated<H> {
+    pub fn new(hitable: H, mut keyframes: Vec<Keyframe>) -> Result<Self, String> {
+        if keyframes.is_empty() {
+            return Err(String::from("animation has no keyframes"))
+        }
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(Animated { hitable, keyframes })
+    }
+
+    fn transform(&self, time: f32) -> Isometry3<f32> {
+        let i = self.keyframes.partition_point(|key| key.time <= time);
+        let (translation, rotation) = if i == 0 {
+            (self.keyframes[0].translation, self.keyframes[0].rotation)
+        } else if i == self.keyframes.len() {
+            (self.keyframes[i - 1].translation, self.keyframes[i - 1].rotation)
+        } else {
+            let (a, b) = (&self.keyframes[i - 1], &self.keyframes[i]);
+            let s = (time - a.time) / (b.time - a.time);
+            // half a turn has no shortest path, keep the rotation of the closest keyframe
+            let rotation = a.rotation.try_slerp(&b.rotation, s, 1e-6)
+                .unwrap_or(if s < 0.5 { a.rotation } else { b.rotation });
+            (a.translation.lerp(&b.translation, s), rotation)
+        };
+        Isometry3::from_parts(Translation3::from(translation), rotation)
+    }
+
+    /// Light sampling has no notion of time, so it uses the middle of the animation.
+    fn sampling_transform(&self) -> Isometry3<f32> {
+        let first = self.keyframes[0].time;
+        let last = self.keyframes[self.keyframes.len() - 1].time;
+        self.transform(0.5 * (first + last))
+    }
+}
+
+impl<H: Hitable> Hitable for Animated<H> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let transform = self.transform(ray.time());
+        let origin = transform.inverse_transform_point(&Point3::from(ray.origin())).coords;
+        let direction = transform.inverse_transform_vector(&ray.direction());
+        self.hitable.hit(&Ray::new(origin, direction, ray.time()), t_min, t_max).map(|mut hit| {
+            hit.p = transform.transform_point(&Point3::from(hit.p)).coords;
+            hit.normal = transform.transform_vector(&hit.normal);
+            hit
+        })
+    }
+
+    /// Bounds the shape at the keyframes and at regular times in between. Translations are linear
+    /// between these times, so only the arcs described by the rotating corners can leave the boxes,
+    /// by at most their sagitta, which pads the result.
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        let bbox = self.hitable.bounding_box(t0, t1)?;
+        let mut times = (0..=BOUNDING_SAMPLES)
+            .map(|i| t0 + (t1 - t0) * i as f32 / BOUNDING_SAMPLES as f32)
+            .chain(self.keyframes.iter().map(|key| key.time).filter(|&t| t > t0 && t < t1))
+            .collect::<Vec<f32>>();
+        times.sort_by(f32::total_cmp);
+        let corners = (0..8).map(|i| Vector3::new(
+            if i & 1 == 0 { bbox.min.x } else { bbox.max.x },
+            if i & 2 == 0 { bbox.min.y } else { bbox.max.y },
+            if i & 4 == 0 { bbox.min.z } else { bbox.max.z })).collect::<Vec<Vector3<f32>>>();
+        let radius = corners.iter().map(|c| c.norm()).fold(0.0, f32::max);
+        let mut result: Option<AABB> = None;
+        let mut max_angle: f32 = 0.0;
+        let mut previous: Option<UnitQuaternion<f32>> = None;
+        for &time in &times {
+            let transform = self.transform(time);
+            if let Some(rotation) = previous {
+                max_angle = max_angle.max(rotation.angle_to(&transform.rotation));
+            }
+            previous = Some(transform.rotation);
+            for corner in &corners {
+                let p = transform.transform_point(&Point3::from(*corner)).coords;
+                let point = AABB::new(p, p);
+                result = Some(result.map_or(point, |b| aabb::surrounding_box(&b, &point)));
+            }
+        }
+        let padding = Vector3::repeat(radius * (1.0 - (0.5 * max_angle).cos()));
+        result.map(|b| AABB::new(b.min - padding, b.max + padding))
+    }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
+        let transform = self.sampling_transform();
+        let o = transform.inverse_transform_point(&Point3::from(o)).coords;
+        self.hitable.pdf_value(o, transform.inverse_transform_vector(&v))
+    }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> {
+        let transform = self.sampling_transform();
+        let o = transform.inverse_transform_point(&Point3::from(o)).coords;
+        transform.transform_vector(&self.hitable.random(o))
+    }
+
+    fn power(&self) -> f32 { self.hitable.power() }
+}
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use nalgebra::{Vector2, Vector3};
+use crate::texture::{ConstantTexture, ImageTexture, Filter, Wrap};
+use crate::image::Image;
+use crate::material::{Material, Lambertian, Metal, Dielectric, DiffuseLight};
+use crate::hitable::HitableList;
+use crate::triangle::{TriangleMesh, Vertex, VertexBuffers};
+
+/// Material properties of a `newmtl` entry of an MTL file.
+struct MtlMaterial {
+    diffuse: Vector3<f32>,
+    specular: Vector3<f32>,
+    emission: Vector3<f32>,
+    shininess: f32,
+    ior: f32,
+    dissolve: f32,
+    illum: u32,
+    diffuse_map: Option<String>
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Vector3::new(0.8, 0.8, 0.8),
+            specular: Vector3::zeros(),
+            emission: Vector3::zeros(),
+            shininess: 0.0,
+            ior: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            diffuse_map: None
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Maps the properties onto the closest material: emissive surfaces become lights,
+    /// transparent ones dielectrics, surfaces with only a specular color metals,
+    /// and everything else lambertian, textured by `map_Kd` if given.
+    fn to_material(&self, dir: &Path) -> Result<Arc<dyn Material>, String> {
+        let material: Arc<dyn Material> = if self.emission.max() > 0.0 {
+            Arc::new(DiffuseLight::new(ConstantTexture::new(self.emission.x, self.emission.y, self.emission.z)))
+        } else if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            Arc::new(Dielectric::new(self.ior))
+        } else if self.illum == 3 || (self.specular.max() > 0.0 && self.diffuse.max() == 0.0) {
+            let fuzz = (2.0 / (self.shininess + 2.0)).sqrt();
+            Arc::new(Metal::new(self.specular, fuzz))
+        } else if let Some(map) = &self.diffuse_map {
+            let path = dir.join(map);
+            let image = Image::open(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            Arc::new(Lambertian::new(ImageTexture::new(&image, Filter::Bilinear, Wrap::Repeat)))
+        } else {
+            Arc::new(Lambertian::new(ConstantTexture::new(self.diffuse.x, self.diffuse.y, self.diffuse.z)))
+        };
+        Ok(material)
+    }
+}
+
+fn numbers<const N: usize>(args: &[&str]) -> Result<[f32; N], String> {
+    if args.len() < N {
+        return Err(format!("expected {} numbers", N))
+    }
+    let mut values = [0.0; N];
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = arg.parse::<f32>().map_err(|_| format!("expected a number, found '{}'", arg))?;
+    }
+    Ok(values)
+}
+
+fn load_mtl(path: &Path, materials: &mut HashMap<String, Arc<dyn Material>>) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut current: Option<(String, MtlMaterial)> = None;
+    let mut finish = |current: Option<(String, MtlMaterial)>| {
+        if let Some((name, mtl)) = current {
+            let material = mtl.to_material(dir).map_err(|err| format!("material '{}': {}", name, err))?;
+            materials.insert(name, material);
+        }
+        Ok::<_, String>(())
+    };
+    for (index, line) in source.lines().enumerate() {
+        let tokens = line.split('#').next().unwrap_or("").split_whitespace().collect::<Vec<&str>>();
+        let (keyword, args) = match tokens.split_first() {
+            Some((keyword, args)) => (*keyword, args),
+            None => continue
+        };
+        let error = |err: String| format!("{}: line {}: {}", path.display(), index + 1, err);
+        if keyword == "newmtl" {
+            finish(current.take())?;
+            let name = args.first().ok_or_else(|| error(String::from("expected material name")))?;
+            current = Some((name.to_string(), MtlMaterial::default()));
+            continue
+        }
+        let mtl = match current.as_mut() {
+            Some((_, mtl)) => mtl,
+            None => return Err(error(format!("'{}' before newmtl", keyword)))
+        };
+        match keyword {
+            "Kd" => mtl.diffuse = Vector3::from(numbers::<3>(args).map_err(error)?),
+            "Ks" => mtl.specular = Vector3::from(numbers::<3>(args).map_err(error)?),
+            "Ke" => mtl.emission = Vector3::from(numbers::<3>(args).map_err(error)?),
+            "Ns" => mtl.shininess = numbers::<1>(args).map_err(error)?[0],
+            "Ni" => mtl.ior = numbers::<1>(args).map_err(error)?[0],
+            "d" => mtl.dissolve = numbers::<1>(args).map_err(error)?[0],
+            "Tr" => mtl.dissolve = 1.0 - numbers::<1>(args).map_err(error)?[0],
+            "illum" => mtl.illum = numbers::<1>(args).map_err(error)?[0] as u32,
+            // options may precede the file name, which comes last
+            "map_Kd" => mtl.diffuse_map = args.last().map(|s| s.to_string()),
+            _ => ()
+        }
+    }
+    finish(current)
+}
+
+/// Faces of a group that use the same material.
+struct Group {
+    name: String,
+    material: Option<String>,
+    faces: Vec<[Vertex; 3]>
+}
+
+/// Parses an index, 1-based or negative relative to the end, into a 0-based one.
+fn index(token: &str, len: usize) -> Result<usize, String> {
+    let i = token.parse::<i64>().map_err(|_| format!("invalid index '{}'", token))?;
+    let index = if i > 0 { i - 1 } else { len as i64 + i };
+    if index < 0 || index >= len as i64 {
+        Err(format!("index {} out of range", i))
+    } else {
+        Ok(index as usize)
+    }
+}
+
+fn vertex(token: &str, buffers: &VertexBuffers) -> Result<Vertex, String> {
+    let mut parts = token.split('/');
+    let position = index(parts.next().unwrap_or(""), buffers.positions.len())?;
+    let uv = match parts.next() {
+        Some(uv) if !uv.is_empty() => Some(index(uv, buffers.uvs.len())?),
+        _ => None
+    };
+    let normal = match parts.next() {
+        Some(normal) if !normal.is_empty() => Some(index(normal, buffers.normals.len())?),
+        _ => None
+    };
+    Ok(Vertex { position, normal, uv })
+}
+
+/// Splits a polygon in triangles by ear clipping, in the plane where its projection is largest.
+/// Falls back to a fan for whatever is left when no ear can be found, as for degenerate polygons.
+fn triangulate(polygon: &[Vertex], positions: &[Vector3<f32>]) -> Vec<[Vertex; 3]> {
+    let n = polygon.len();
+    if n == 3 {
+        return vec![[polygon[0], polygon[1], polygon[2]]]
+    }
+    // Newell's method gives the polygon normal, whatever its convexity
+    let mut normal = Vector3::zeros();
+    for i in 0..n {
+        let p = positions[polygon[i].position];
+        let q = positions[polygon[(i + 1) % n].position];
+        normal += Vector3::new((p.y - q.y) * (p.z + q.z), (p.z - q.z) * (p.x + q.x), (p.x - q.x) * (p.y + q.y));
+    }
+    let axis = normal.iamax();
+    let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+    let sign = normal[axis].signum();
+    let project = |v: &Vertex| Vector2::new(positions[v.position][a], positions[v.position][b]);
+    let cross = |o: Vector2<f32>, p: Vector2<f32>, q: Vector2<f32>| sign * (p - o).perp(&(q - o));
+
+    let mut remaining = polygon.to_vec();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let (p0, p1, p2) = (project(&remaining[(i + m - 1) % m]), project(&remaining[i]), project(&remaining[(i + 1) % m]));
+            cross(p0, p1, p2) > 0.0 && (0..m).filter(|&j| j != i && j != (i + m - 1) % m && j != (i + 1) % m).all(|j| {
+                let p = project(&remaining[j]);
+                cross(p0, p1, p) < 0.0 || cross(p1, p2, p) < 0.0 || cross(p2, p0, p) < 0.0
+            })
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]]);
+                remaining.remove(i);
+            },
+            None => break
+        }
+    }
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+    triangles
+}
+
+/// Loads a Wavefront OBJ file, with the materials of the MTL files it references.
+/// Each group gets a mesh for each material it uses; all of them share the vertex buffers.
+pub fn load(path: &Path) -> Result<HitableList, String> {
+    let source = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut buffers = VertexBuffers::default();
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut groups: Vec<Group> = Vec::new();
+    let mut group = String::from("default");
+    let mut material: Option<String> = None;
+    for (index, line) in source.lines().enumerate() {
+        let tokens = line.split('#').next().unwrap_or("").split_whitespace().collect::<Vec<&str>>();
+        let (keyword, args) = match tokens.split_first() {
+            Some((keyword, args)) => (*keyword, args),
+            None => continue
+        };
+        let error = |err: String| format!("{}: line {}: {}", path.display(), index + 1, err);
+        match keyword {
+            "v" => buffers.positions.push(Vector3::from(numbers::<3>(args).map_err(error)?)),
+            "vn" => {
+                let normal = Vector3::from(numbers::<3>(args).map_err(error)?).try_normalize(0.0)
+                    .ok_or_else(|| error(String::from("normal of zero length")))?;
+                buffers.normals.push(normal);
+            },
+            "vt" => {
+                let uv = numbers::<1>(args).map_err(error)?[0];
+                let v = args.get(1).and_then(|v| v.parse::<f32>().ok()).unwrap_or(0.0);
+                buffers.uvs.push(Vector2::new(uv, v));
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error(String::from("face with less than 3 vertices")))
+                }
+                let polygon = args.iter().map(|arg| vertex(arg, &buffers)).collect::<Result<Vec<Vertex>, String>>()
+                    .map_err(error)?;
+                let i = match groups.iter().position(|g| g.name == group && g.material == material) {
+                    Some(i) => i,
+                    None => {
+                        groups.push(Group { name: group.clone(), material: material.clone(), faces: Vec::new() });
+                        groups.len() - 1
+                    }
+                };
+                groups[i].faces.extend(triangulate(&polygon, &buffers.positions));
+            },
+            "g" | "o" => group = args.join(" "),
+            "usemtl" => material = args.first().map(|s| s.to_string()),
+            "mtllib" => for lib in args {
+                load_mtl(&dir.join(lib), &mut materials)?;
+            },
+            _ => ()
+        }
+    }
+    let default: Arc<dyn Material> = Arc::new(Lambertian::new(ConstantTexture::new(0.8, 0.8, 0.8)));
+    let buffers = Arc::new(buffers);
+    let mut list = HitableList::default();
+    for Group { name, material, faces } in groups {
+        let material = match material {
+            Some(material) => materials.get(&material).cloned().ok_or_else(||
+                format!("{}: unknown material '{}' in group '{}'", path.display(), material, name))?,
+            None => default.clone()
+        };
+        let mesh = TriangleMesh::new(buffers.clone(), faces, material)
+            .map_err(|err| format!("{}: group '{}': {}", path.display(), name, err))?;
+        list.push(mesh);
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+    use super::*;
+    use crate::ray::Ray;
+    use crate::hitable::HitRecord;
+    use crate::material::ScatterRecord;
+
+    /// Writes `files` into a new temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir = env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            for (file, contents) in files {
+                fs::write(dir.join(file), contents).unwrap();
+            }
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) { fs::remove_dir_all(&self.0).ok(); }
+    }
+
+    #[test]
+    fn triangulate_concave_polygon() {
+        // a U, whose fan from the first corner would cover the notch
+        let positions = [(0.0, 0.0), (3.0, 0.0), (3.0, 2.0), (2.0, 2.0), (2.0, 1.0), (1.0, 1.0), (1.0, 2.0), (0.0, 2.0)]
+            .iter().map(|&(x, y)| Vector3::new(x, y, 1.0)).collect::<Vec<_>>();
+        let polygon = (0..positions.len()).map(|position| Vertex { position, normal: None, uv: None }).collect::<Vec<_>>();
+        let triangles = triangulate(&polygon, &positions);
+        assert_eq!(triangles.len(), positions.len() - 2);
+        let mut area = 0.0;
+        for [a, b, c] in triangles {
+            let (p0, p1, p2) = (positions[a.position], positions[b.position], positions[c.position]);
+            let cross = (p1 - p0).cross(&(p2 - p0));
+            assert!(cross.z > 0.0, "triangle keeps the winding of the polygon");
+            area += 0.5 * cross.z;
+        }
+        assert!((area - 5.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn mtl_materials() {
+        let dir = TempDir::new("mtl-materials", &[
+            ("materials.mtl", "\
+newmtl plain
+Kd 0.1 0.2 0.3
+newmtl painted
+Kd 0.5 0.5 0.5
+map_Kd -s 1 1 1 red.ppm
+newmtl steel
+illum 3
+Ks 0.5 0.6 0.7
+Ns 1000
+newmtl glass
+Ni 1.5
+d 0.5
+newmtl lamp
+Ke 4 4 4
+"),
+            ("red.ppm", "P3 1 1 255 255 0 0")
+        ]);
+        let mut materials = HashMap::new();
+        load_mtl(&dir.0.join("materials.mtl"), &mut materials).unwrap();
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0), 0.0);
+        let attenuation = |name: &str| {
+            let material = &materials[name];
+            let hit = HitRecord { t: 1.0, u: 0.5, v: 0.5, p: Vector3::zeros(), normal: Vector3::new(0.0, 0.0, 1.0), material: material.as_ref() };
+            match material.scatter(&ray, &hit) {
+                Some(ScatterRecord::Scatter { attenuation, .. }) => ("diffuse", attenuation),
+                Some(ScatterRecord::Specular { attenuation, .. }) => ("specular", attenuation),
+                None => ("emissive", material.emitted(&ray, &hit))
+            }
+        };
+        assert_eq!(attenuation("plain"), ("diffuse", Vector3::new(0.1, 0.2, 0.3)));
+        assert_eq!(attenuation("painted"), ("diffuse", Vector3::new(1.0, 0.0, 0.0)));
+        assert_eq!(attenuation("steel"), ("specular", Vector3::new(0.5, 0.6, 0.7)));
+        assert_eq!(attenuation("glass"), ("specular", Vector3::new(1.0, 1.0, 1.0)));
+        assert_eq!(attenuation("lamp"), ("emissive", Vector3::new(4.0, 4.0, 4.0)));
+    }
+
+    #[test]
+    fn errors() {
+        let dir = TempDir::new("obj-errors", &[
+            ("normal.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 0\nf 1//1 2//1 3//1\n"),
+            ("texture.obj", "mtllib texture.mtl\n"),
+            ("texture.mtl", "newmtl painted\nmap_Kd missing.ppm\n")
+        ]);
+        let error = |file: &str| match load(&dir.0.join(file)) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err
+        };
+        assert!(error("normal.obj").ends_with("line 4: normal of zero length"));
+        assert!(error("texture.obj").contains("material 'painted'"));
+    }
+}
@@ -1,5 +1,6 @@
 use nalgebra::Vector3;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct ONB {
     axis: [Vector3<f32>; 3]
 }
@@ -13,6 +14,16 @@ impl ONB {
         ONB { axis: [u, v, w] }
     }
 
+    /// Basis around `n` whose `u` axis is `t` projected on the plane orthogonal to `n`, or an arbitrary
+    /// axis of that plane when `t` is parallel to `n`.
+    pub fn build_from_wu(n: &Vector3<f32>, t: &Vector3<f32>) -> Self {
+        let w = n.normalize();
+        match (t - w * w.dot(t)).try_normalize(1e-6) {
+            Some(u) => ONB { axis: [u, w.cross(&u), w] },
+            None => ONB::build_from_w(n)
+        }
+    }
+
     pub fn u(&self) -> Vector3<f32> { self.axis[0] }
     pub fn v(&self) -> Vector3<f32> { self.axis[1] }
     pub fn w(&self) -> Vector3<f32> { self.axis[2] }
@@ -20,4 +31,9 @@ impl ONB {
     pub fn local(&self, a: &Vector3<f32>) -> Vector3<f32> {
         a.x * self.u() + a.y * self.v() + a.z * self.w()
     }
+
+    /// Coordinates of a world vector in the basis, the inverse of `local`.
+    pub fn to_local(&self, a: &Vector3<f32>) -> Vector3<f32> {
+        Vector3::new(a.dot(&self.u()), a.dot(&self.v()), a.dot(&self.w()))
+    }
 }
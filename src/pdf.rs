@@ -2,23 +2,42 @@ use std::f32;
 use nalgebra::Vector3;
 use rand::Rng;
 use crate::onb::ONB;
+use crate::random;
 use crate::hitable::Hitable;
+use crate::environment::Environment;
+use crate::microfacet::{Ggx, RoughInterface};
+use crate::distribution::Distribution1D;
 
 fn random_cosine_direction() -> Vector3<f32> {
-    let mut rng = rand::thread_rng();
+    let mut rng = random::rng();
     let r1 = rng.gen::<f32>();
     let r2 = rng.gen::<f32>();
     let z = (1.0 - r2).sqrt();
     let phi = 2.0 * f32::consts::PI * r1;
-    let x = phi.cos() * 2.0 * r2.sqrt();
-    let y = phi.sin() * 2.0 * r2.sqrt();
+    let x = phi.cos() * r2.sqrt();
+    let y = phi.sin() * r2.sqrt();
     Vector3::new(x, y, z)
 }
 
+pub fn random_unit_vector() -> Vector3<f32> {
+    let mut rng = random::rng();
+    let z = 1.0 - 2.0 * rng.gen::<f32>();
+    let r = (1.0 - z.powi(2)).max(0.0).sqrt();
+    let phi = 2.0 * f32::consts::PI * rng.gen::<f32>();
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub enum PDF<'a> {
     Cosine { uvw: ONB },
-    Hitable { origin: Vector3<f32>, hitable: &'a Box<dyn Hitable> },
-    Mixture { p: &'a PDF<'a>, q: &'a PDF<'a> }
+    Uniform,
+    Hitable { origin: Vector3<f32>, hitable: &'a dyn Hitable },
+    Environment { environment: &'a dyn Environment },
+    /// Mirror reflections of `wo` off microfacet normals visible from it, in the local basis `uvw`.
+    Microfacet { uvw: ONB, wo: Vector3<f32>, distribution: Ggx },
+    /// Reflections and refractions of `wo` through a rough interface, in the local basis `uvw`.
+    Dielectric { uvw: ONB, wo: Vector3<f32>, interface: RoughInterface },
+    Mixture { components: Vec<PDF<'a>>, weights: &'a Distribution1D }
 }
 
 impl<'a> PDF<'a> {
@@ -26,45 +45,103 @@ impl<'a> PDF<'a> {
         PDF::Cosine { uvw: ONB::build_from_w(&w) }
     }
 
-    pub fn hitable(hitable: &'a Box<dyn Hitable>, origin: Vector3<f32>) -> Self {
+    /// Same density for all directions.
+    pub fn uniform() -> Self { PDF::Uniform }
+
+    pub fn hitable(hitable: &'a dyn Hitable, origin: Vector3<f32>) -> Self {
         PDF::Hitable { origin, hitable }
     }
 
-    pub fn mixture(p: &'a PDF, q: &'a PDF) -> Self {
-        PDF::Mixture { p, q }
+    pub fn environment(environment: &'a dyn Environment) -> Self {
+        PDF::Environment { environment }
+    }
+
+    /// Reflections of the direction `wo` leaving the surface, in the frame `uvw` of the distribution.
+    pub fn microfacet(uvw: ONB, wo: Vector3<f32>, distribution: Ggx) -> Self {
+        PDF::Microfacet { wo: uvw.to_local(&wo).normalize(), uvw, distribution }
+    }
+
+    /// Directions scattered through `interface` from the direction `wo` leaving it.
+    pub fn dielectric(uvw: ONB, wo: Vector3<f32>, interface: RoughInterface) -> Self {
+        PDF::Dielectric { wo: uvw.to_local(&wo).normalize(), uvw, interface }
+    }
+
+    /// Sum of densities weighted by the probabilities of a distribution with one index per component.
+    pub fn mixture(components: Vec<PDF<'a>>, weights: &'a Distribution1D) -> Self {
+        assert_eq!(components.len(), weights.len(), "mixture needs a weight per component");
+        PDF::Mixture { components, weights }
     }
 
     pub fn value(&self, direction: Vector3<f32>) -> f32 {
         match self {
             PDF::Cosine { uvw } => {
                 let cosine = direction.normalize().dot(&uvw.w());
-                if cosine > 0.0 {
-                    cosine / f32::consts::PI
-                } else {
-                    1.0
-                }
+                if cosine > 0.0 { cosine / f32::consts::PI } else { 0.0 }
             },
+            PDF::Uniform => 1.0 / (4.0 * f32::consts::PI),
             PDF::Hitable { origin, hitable } => {
                 hitable.pdf_value(*origin, direction)
             },
-            PDF::Mixture { p, q } => {
-                0.5 * p.value(direction) + 0.5 * q.value(direction)
+            PDF::Environment { environment } => environment.pdf_value(&direction),
+            PDF::Microfacet { uvw, wo, distribution } => {
+                // the reflection maps normals to directions with a jacobian of 1 / (4 wo.h)
+                let h = (wo + uvw.to_local(&direction).normalize()).normalize();
+                let cosine = wo.dot(&h);
+                if cosine > 0.0 { distribution.visible_normal_pdf(wo, &h) / (4.0 * cosine) } else { 0.0 }
+            },
+            PDF::Dielectric { uvw, wo, interface } => interface.pdf(wo, &uvw.to_local(&direction).normalize()),
+            PDF::Mixture { components, weights } => {
+                // components that are never sampled can be skipped, and their density may be undefined
+                components.iter().enumerate().map(|(i, pdf)| {
+                    let weight = weights.pmf(i);
+                    if weight > 0.0 { weight * pdf.value(direction) } else { 0.0 }
+                }).sum()
             }
         }
     }
 
+    /// Direction drawn with this density, or zero when a rough dielectric finds none.
     pub fn generate(&self) -> Vector3<f32> {
         match self {
             PDF::Cosine { uvw } => {
                 uvw.local(&random_cosine_direction())
             },
+            PDF::Uniform => random_unit_vector(),
             PDF::Hitable { origin, hitable } => {
                 hitable.random(*origin)
             },
-            PDF::Mixture { p, q } => {
-                let mut rng = rand::thread_rng();
-                if rng.gen::<bool>() { p.generate() } else { q.generate() }
+            PDF::Environment { environment } => environment.random(),
+            PDF::Microfacet { uvw, wo, distribution } => {
+                let mut rng = random::rng();
+                let h = distribution.sample_visible_normal(wo, rng.gen::<f32>(), rng.gen::<f32>());
+                uvw.local(&(2.0 * wo.dot(&h) * h - wo))
+            },
+            PDF::Dielectric { uvw, wo, interface } => {
+                let mut rng = random::rng();
+                interface.sample(wo, [rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()])
+                    .map_or(Vector3::zeros(), |wi| uvw.local(&wi))
+            },
+            PDF::Mixture { components, weights } => {
+                match components.get(weights.sample(random::rng().gen::<f32>())) {
+                    Some(pdf) => pdf.generate(),
+                    None => Vector3::new(1.0, 0.0, 0.0)
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cosine_directions_are_unit_vectors() {
+        let pdf = PDF::cosine(Vector3::new(0.0, 1.0, 0.0));
+        for _ in 0..1000 {
+            let direction = pdf.generate();
+            assert!((direction.norm() - 1.0).abs() < 1e-4);
+            assert!(pdf.value(direction) > 0.0);
+        }
+    }
+}
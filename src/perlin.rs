@@ -0,0 +1,86 @@
+use nalgebra::Vector3;
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+const POINT_COUNT: usize = 256;
+
+fn perlin_generate_perm(rng: &mut impl Rng) -> Vec<usize> {
+    let mut p = (0..POINT_COUNT).collect::<Vec<usize>>();
+    p.shuffle(rng);
+    p
+}
+
+fn perlin_interp(c: &[[[Vector3<f32>; 2]; 2]; 2], u: f32, v: f32, w: f32) -> f32 {
+    let uu = u * u * (3.0 - 2.0 * u);
+    let vv = v * v * (3.0 - 2.0 * v);
+    let ww = w * w * (3.0 - 2.0 * w);
+    let mut accum = 0.0;
+    for (i, ci) in c.iter().enumerate() {
+        for (j, cij) in ci.iter().enumerate() {
+            for (k, cijk) in cij.iter().enumerate() {
+                let weight = Vector3::new(u - i as f32, v - j as f32, w - k as f32);
+                accum += (i as f32 * uu + (1 - i) as f32 * (1.0 - uu))
+                    * (j as f32 * vv + (1 - j) as f32 * (1.0 - vv))
+                    * (k as f32 * ww + (1 - k) as f32 * (1.0 - ww))
+                    * cijk.dot(&weight);
+            }
+        }
+    }
+    accum
+}
+
+/// Gradient noise on a lattice of random unit vectors.
+#[derive(Clone)]
+pub struct Perlin {
+    ranvec: Vec<Vector3<f32>>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>
+}
+
+impl Perlin {
+    pub fn new(rng: &mut impl Rng) -> Self {
+        let ranvec = (0..POINT_COUNT).map(|_| {
+            Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize()
+        }).collect();
+        let perm_x = perlin_generate_perm(rng);
+        let perm_y = perlin_generate_perm(rng);
+        let perm_z = perlin_generate_perm(rng);
+        Perlin { ranvec, perm_x, perm_y, perm_z }
+    }
+
+    /// Noise value in [-1, 1].
+    pub fn noise(&self, p: &Vector3<f32>) -> f32 {
+        let u = p.x - p.x.floor();
+        let v = p.y - p.y.floor();
+        let w = p.z - p.z.floor();
+        let i = p.x.floor() as i64;
+        let j = p.y.floor() as i64;
+        let k = p.z.floor() as i64;
+        let mut c = [[[Vector3::zeros(); 2]; 2]; 2];
+        for (di, ci) in c.iter_mut().enumerate() {
+            for (dj, cij) in ci.iter_mut().enumerate() {
+                for (dk, cijk) in cij.iter_mut().enumerate() {
+                    let index = self.perm_x[((i + di as i64) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i64) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i64) & 255) as usize];
+                    *cijk = self.ranvec[index];
+                }
+            }
+        }
+        perlin_interp(&c, u, v, w)
+    }
+
+    /// Sum of `depth` octaves of noise, each with double frequency and half amplitude.
+    pub fn turb(&self, p: &Vector3<f32>, depth: usize) -> f32 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p *= 2.0;
+        }
+        accum.abs()
+    }
+}
@@ -0,0 +1,83 @@
+use std::io;
+use std::io::{Read, Write};
+use nalgebra::Vector3;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads a color or grayscale PFM of either endianness, returning its rows from the top.
+pub fn read(reader: &mut impl Read) -> io::Result<(usize, usize, Vec<Vector3<f32>>)> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    // the header is three whitespace separated tokens and a number, followed by a single whitespace
+    let mut pos = 0;
+    let mut tokens = Vec::new();
+    while tokens.len() < 4 {
+        while pos < data.len() && data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid("truncated pfm header"))
+        }
+        tokens.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+    let channels = match tokens[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid("not a pfm file"))
+    };
+    let number = |token: &str| token.parse::<usize>().map_err(|_| invalid("invalid pfm size"));
+    let (width, height) = (number(&tokens[1])?, number(&tokens[2])?);
+    let scale = tokens[3].parse::<f32>().map_err(|_| invalid("invalid pfm scale"))?;
+    let size = width.checked_mul(height).and_then(|pixels| pixels.checked_mul(4 * channels))
+        .ok_or_else(|| invalid("invalid pfm size"))?;
+    let samples = data.get(pos + 1..).and_then(|data| data.get(..size)).ok_or_else(|| invalid("truncated pfm data"))?;
+    let values = samples.chunks(4).map(|b| {
+        let bytes = [b[0], b[1], b[2], b[3]];
+        if scale < 0.0 { f32::from_le_bytes(bytes) } else { f32::from_be_bytes(bytes) }
+    }).collect::<Vec<f32>>();
+    let mut pixels = values.chunks(channels)
+        .map(|c| if channels == 3 { Vector3::new(c[0], c[1], c[2]) } else { Vector3::repeat(c[0]) })
+        .collect::<Vec<Vector3<f32>>>();
+    if width > 0 {
+        pixels = pixels.chunks(width).rev().flatten().copied().collect();
+    }
+    Ok((width, height, pixels))
+}
+
+/// Writes a little endian color PFM; rows are stored from the bottom, as the format requires.
+pub fn write(writer: &mut impl Write, width: usize, height: usize, pixels: &[Vector3<f32>]) -> io::Result<()> {
+    write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+    for row in pixels.chunks(width).rev() {
+        for pixel in row {
+            for c in pixel.iter() {
+                writer.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let pixels = vec![Vector3::new(0.5, 1.0, 2.0), Vector3::new(-1.0, 0.0, 4.0)];
+        let mut data = Vec::new();
+        write(&mut data, 1, 2, &pixels).unwrap();
+        assert_eq!(read(&mut data.as_slice()).unwrap(), (1, 2, pixels));
+    }
+
+    #[test]
+    fn oversized_header_is_invalid() {
+        let header = format!("PF\n{} {}\n-1.0\n", usize::MAX / 2, 3);
+        assert_eq!(read(&mut header.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
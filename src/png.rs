@@ -0,0 +1,273 @@
+use std::io;
+use std::io::{Read, Write};
+use crate::zlib;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn write_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut chunk = Vec::with_capacity(data.len() + 4);
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    writer.write_all(&chunk)?;
+    writer.write_all(&crc32(&chunk).to_be_bytes())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// Applies the five PNG filters to a row and keeps the one with the smallest
+/// sum of absolute differences, the heuristic suggested by the specification.
+fn filter_row(row: &[u8], prior: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    let mut best: Option<(u64, u8, Vec<u8>)> = None;
+    for filter in 0..5u8 {
+        let filtered = row.iter().enumerate().map(|(i, &x)| {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = prior[i];
+            let c = if i >= bpp { prior[i - bpp] } else { 0 };
+            match filter {
+                0 => x,
+                1 => x.wrapping_sub(a),
+                2 => x.wrapping_sub(b),
+                3 => x.wrapping_sub(((a as u16 + b as u16) / 2) as u8),
+                _ => x.wrapping_sub(paeth(a, b, c))
+            }
+        }).collect::<Vec<u8>>();
+        let cost = filtered.iter().map(|&x| (x as i8).unsigned_abs() as u64).sum::<u64>();
+        if best.as_ref().is_none_or(|(best_cost, _, _)| cost < *best_cost) {
+            best = Some((cost, filter, filtered));
+        }
+    }
+    let (_, filter, filtered) = best.unwrap();
+    out.push(filter);
+    out.extend_from_slice(&filtered);
+}
+
+/// Writes 8 bit RGB `pixels`, stored row by row from the top.
+pub fn write(writer: &mut impl Write, width: usize, height: usize, pixels: &[u8]) -> io::Result<()> {
+    writer.write_all(&SIGNATURE)?;
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(writer, b"IHDR", &header)?;
+    let stride = 3 * width;
+    let mut filtered = Vec::with_capacity((stride + 1) * height);
+    let mut prior = vec![0u8; stride];
+    for row in pixels.chunks(stride) {
+        filter_row(row, &prior, 3, &mut filtered);
+        prior.copy_from_slice(row);
+    }
+    write_chunk(writer, b"IDAT", &zlib::compress(&filtered))?;
+    write_chunk(writer, b"IEND", &[])
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn unfilter_row(filter: u8, row: &mut [u8], prior: &[u8], bpp: usize) -> io::Result<()> {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prior[i];
+        let c = if i >= bpp { prior[i - bpp] } else { 0 };
+        row[i] = match filter {
+            0 => row[i],
+            1 => row[i].wrapping_add(a),
+            2 => row[i].wrapping_add(b),
+            3 => row[i].wrapping_add(((a as u16 + b as u16) / 2) as u8),
+            4 => row[i].wrapping_add(paeth(a, b, c)),
+            _ => return Err(invalid("invalid png filter"))
+        };
+    }
+    Ok(())
+}
+
+/// Sample `index` of an unfiltered row, scaled to 8 bits unless it is a palette index.
+fn sample(row: &[u8], index: usize, bit_depth: u8, palette: bool) -> u8 {
+    match bit_depth {
+        8 => row[index],
+        16 => row[2 * index],
+        _ => {
+            let per_byte = 8 / bit_depth as usize;
+            let shift = 8 - bit_depth as usize * (1 + index % per_byte);
+            let value = (row[index / per_byte] >> shift) & ((1 << bit_depth) - 1);
+            if palette { value } else { (value as u16 * 255 / ((1 << bit_depth) - 1)) as u8 }
+        }
+    }
+}
+
+/// Adam7 passes, as starting column and row and their steps.
+const ADAM7: [(usize, usize, usize, usize); 7] =
+    [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+/// Reads a png image of any color type and bit depth, interlaced or not,
+/// as 8 bit RGB pixels stored row by row from the top. Alpha is ignored.
+pub fn read(reader: &mut impl Read) -> io::Result<(usize, usize, Vec<u8>)> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    if data.len() < 8 || data[..8] != SIGNATURE {
+        return Err(invalid("not a png file"))
+    }
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    while pos + 12 <= data.len() {
+        let length = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let chunk = data.get(pos + 4..pos + 8 + length).ok_or_else(|| invalid("truncated png chunk"))?;
+        let crc = data.get(pos + 8 + length..pos + 12 + length).ok_or_else(|| invalid("truncated png chunk"))?;
+        if crc32(chunk) != u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+            return Err(invalid("png chunk crc mismatch"))
+        }
+        let (kind, content) = chunk.split_at(4);
+        match kind {
+            b"IHDR" if content.len() == 13 => header = Some((
+                u32::from_be_bytes([content[0], content[1], content[2], content[3]]) as usize,
+                u32::from_be_bytes([content[4], content[5], content[6], content[7]]) as usize,
+                content[8], content[9], content[12])),
+            b"PLTE" => palette = content.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(content),
+            b"IEND" => break,
+            _ => ()
+        }
+        pos += 12 + length;
+    }
+    let (width, height, bit_depth, color_type, interlace) = header.ok_or_else(|| invalid("missing png header"))?;
+    if width == 0 || height == 0 {
+        return Err(invalid("empty png image"))
+    }
+    let size = width.checked_mul(height).and_then(|n| n.checked_mul(3)).ok_or_else(|| invalid("png image too large"))?;
+    let channels = match (color_type, bit_depth) {
+        (0, 1 | 2 | 4 | 8 | 16) => 1,
+        (2, 8 | 16) => 3,
+        (3, 1 | 2 | 4 | 8) => 1,
+        (4, 8 | 16) => 2,
+        (6, 8 | 16) => 4,
+        _ => return Err(invalid("unsupported png color type or bit depth"))
+    };
+    if color_type == 3 && palette.is_empty() {
+        return Err(invalid("missing png palette"))
+    }
+    let raw = zlib::decompress(&compressed).map_err(|err| invalid(&err))?;
+    let bits_per_pixel = channels * bit_depth as usize;
+    let bpp = bits_per_pixel.div_ceil(8);
+    let passes = if interlace == 1 { ADAM7.to_vec() } else { vec![(0, 0, 1, 1)] };
+    let mut pixels = vec![0u8; size];
+    let mut pos = 0;
+    for (x0, y0, dx, dy) in passes {
+        let pass_width = (width + dx - 1 - x0) / dx;
+        let pass_height = (height + dy - 1 - y0) / dy;
+        if pass_width == 0 || pass_height == 0 {
+            continue
+        }
+        let stride = (pass_width * bits_per_pixel).div_ceil(8);
+        let mut prior = vec![0u8; stride];
+        for y in 0..pass_height {
+            let filter = *raw.get(pos).ok_or_else(|| invalid("truncated png data"))?;
+            let mut row = raw.get(pos + 1..pos + 1 + stride).ok_or_else(|| invalid("truncated png data"))?.to_vec();
+            pos += 1 + stride;
+            unfilter_row(filter, &mut row, &prior, bpp)?;
+            for x in 0..pass_width {
+                let i = 3 * ((y0 + y * dy) * width + x0 + x * dx);
+                let rgb = match color_type {
+                    3 => {
+                        let index = 3 * sample(&row, x, bit_depth, true) as usize;
+                        let color = palette.get(index..index + 3).ok_or_else(|| invalid("png palette index out of range"))?;
+                        [color[0], color[1], color[2]]
+                    },
+                    0 | 4 => [sample(&row, x * channels, bit_depth, false); 3],
+                    _ => [0, 1, 2].map(|c| sample(&row, x * channels + c, bit_depth, false))
+                };
+                pixels[i..i + 3].copy_from_slice(&rgb);
+            }
+            prior = row;
+        }
+    }
+    Ok((width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn file_layout() {
+        let mut file = Vec::new();
+        write(&mut file, 3, 2, &[128; 18]).unwrap();
+        assert_eq!(file[..8], SIGNATURE);
+        let mut rest = &file[8..];
+        let mut kinds = Vec::new();
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let chunk = &rest[4..8 + len];
+            assert_eq!(rest[8 + len..12 + len], crc32(chunk).to_be_bytes());
+            if &chunk[..4] == b"IHDR" {
+                assert_eq!(chunk[4..], [0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+            }
+            kinds.push(chunk[..4].to_vec());
+            rest = &rest[12 + len..];
+        }
+        assert_eq!(kinds, [b"IHDR".to_vec(), b"IDAT".to_vec(), b"IEND".to_vec()]);
+    }
+
+    #[test]
+    fn round_trip() {
+        // gradients, flat areas and noise, so that rows pick different filters
+        let (width, height) = (37, 23);
+        let mut state = 1u32;
+        let pixels = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).flat_map(|(x, y)| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            match y % 3 {
+                0 => [(x * 7) as u8, (y * 11) as u8, (x * y) as u8],
+                1 => [200, 100, 50],
+                _ => [(state >> 16) as u8, (state >> 8) as u8, (state >> 24) as u8]
+            }
+        }).collect::<Vec<u8>>();
+        let mut file = Vec::new();
+        write(&mut file, width, height, &pixels).unwrap();
+        assert_eq!(read(&mut file.as_slice()).unwrap(), (width, height, pixels));
+    }
+
+    #[test]
+    fn corrupted_file() {
+        let mut file = Vec::new();
+        write(&mut file, 2, 2, &[0; 12]).unwrap();
+        assert!(read(&mut &file[1..]).is_err());
+        let last = file.len() - 1;
+        file[last] ^= 1;
+        assert!(read(&mut file.as_slice()).is_err());
+    }
+
+    #[test]
+    fn empty_image() {
+        let mut file = Vec::new();
+        write(&mut file, 1, 1, &[0; 3]).unwrap();
+        // zero width in the header, with its crc fixed up
+        file[16..20].copy_from_slice(&0u32.to_be_bytes());
+        let crc = crc32(&file[12..29]);
+        file[29..33].copy_from_slice(&crc.to_be_bytes());
+        assert_eq!(read(&mut file.as_slice()).unwrap_err().to_string(), "empty png image");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,31 @@
+use std::cell::RefCell;
+use rand::{RngCore, SeedableRng, Error};
+use rand::rngs::StdRng;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Handle to the random number generator of the current thread.
+/// Unlike `rand::thread_rng()` it can be reseeded, so that renders are reproducible.
+pub struct SceneRng;
+
+pub fn rng() -> SceneRng { SceneRng }
+
+/// Reseeds the generator of the current thread.
+/// The render loop calls this once per pixel, so results don't depend on thread scheduling.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed))
+}
+
+impl RngCore for SceneRng {
+    fn next_u32(&mut self) -> u32 { RNG.with(|rng| rng.borrow_mut().next_u32()) }
+
+    fn next_u64(&mut self) -> u64 { RNG.with(|rng| rng.borrow_mut().next_u64()) }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) { RNG.with(|rng| rng.borrow_mut().fill_bytes(dest)) }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        RNG.with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+    }
+}
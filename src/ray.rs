@@ -3,16 +3,23 @@ use nalgebra::Vector3;
 pub struct Ray {
     a: Vector3<f32>,
     b: Vector3<f32>,
-    time: f32
+    time: f32,
+    wavelength: Option<f32>
 }
 
 impl Ray {
     pub fn new (a: Vector3<f32>, b: Vector3<f32>, time: f32) -> Self {
-        Ray { a, b, time }
+        Ray { a, b, time, wavelength: None }
+    }
+
+    /// The same ray carrying only light of `wavelength` in nanometers, or all the visible light for none.
+    pub fn with_wavelength(&self, wavelength: Option<f32>) -> Self {
+        Ray { wavelength, ..*self }
     }
 
     pub fn origin(&self) -> Vector3<f32> { self.a }
     pub fn direction(&self) -> Vector3<f32> { self.b }
     pub fn time(&self) -> f32 { self.time }
+    pub fn wavelength(&self) -> Option<f32> { self.wavelength }
     pub fn point_at_parameter(&self, t: f32) -> Vector3<f32> { self.a + t * self.b }
-}
\ No newline at end of file
+}
@@ -2,9 +2,11 @@ use std::f32;
 use nalgebra::Vector3;
 use rand::Rng;
 use crate::ray::Ray;
+use crate::random;
 use crate::hitable::{Hitable, HitRecord};
 use crate::material::Material;
 use crate::aabb::AABB;
+use crate::color;
 
 #[derive(Clone)]
 pub enum Plane {
@@ -39,7 +41,7 @@ impl<M: Material> AARect<M> {
 }
 
 impl<M: Material> Hitable for AARect<M> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let (k_axis, a_axis, b_axis) = get_axis(&self.plane);
         let t = (self.k - ray.origin()[k_axis]) / ray.direction()[k_axis];
         if t < t_min || t > t_max {
@@ -61,8 +63,15 @@ impl<M: Material> Hitable for AARect<M> {
     }
 
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
-        let min = Vector3::new(self.a0, self.b0, self.k - 0.0001);
-        let max = Vector3::new(self.a1, self.b1, self.k + 0.0001);
+        let (k_axis, a_axis, b_axis) = get_axis(&self.plane);
+        let mut min = Vector3::zeros();
+        let mut max = Vector3::zeros();
+        min[a_axis] = self.a0;
+        max[a_axis] = self.a1;
+        min[b_axis] = self.b0;
+        max[b_axis] = self.b1;
+        min[k_axis] = self.k - 0.0001;
+        max[k_axis] = self.k + 0.0001;
         Some(AABB { min, max })
     }
 
@@ -78,7 +87,7 @@ impl<M: Material> Hitable for AARect<M> {
     }
 
     fn random(&self, o: Vector3<f32>) -> Vector3<f32> {
-        let mut rng = rand::thread_rng();
+        let mut rng = random::rng();
         let (k_axis, a_axis, b_axis) = get_axis(&self.plane);
         let mut random_point = Vector3::zeros();
         random_point[a_axis] = rng.gen_range(self.a0..self.a1);
@@ -86,4 +95,8 @@ impl<M: Material> Hitable for AARect<M> {
         random_point[k_axis] = self.k;
         random_point - o
     }
+
+    fn power(&self) -> f32 {
+        (self.a1 - self.a0) * (self.b1 - self.b0) * color::luminance(&self.material.emission())
+    }
 }
\ No newline at end of file
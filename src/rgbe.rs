@@ -0,0 +1,184 @@
+use std::io;
+use std::io::{BufRead, Write};
+use nalgebra::Vector3;
+
+const MIN_RUN: usize = 4;
+const MAX_RUN: usize = 127;
+
+/// Shared exponent encoding of a color: the mantissas of the largest component
+/// use the full byte, the other components are scaled by the same exponent.
+fn to_rgbe(color: &Vector3<f32>) -> [u8; 4] {
+    let v = color.max();
+    if v < 1e-32 {
+        [0, 0, 0, 0]
+    } else {
+        let exponent = v.log2().floor() as i32 + 1;
+        let scale = 256.0 / 2f32.powi(exponent);
+        let mantissa = |c: f32| (c.max(0.0) * scale).min(255.0) as u8;
+        [mantissa(color.x), mantissa(color.y), mantissa(color.z), (exponent + 128) as u8]
+    }
+}
+
+/// Inverse of `to_rgbe`, taking the middle of the range each mantissa stands for.
+fn from_rgbe(rgbe: &[u8]) -> Vector3<f32> {
+    if rgbe[3] == 0 {
+        Vector3::zeros()
+    } else {
+        let scale = 2f32.powi(rgbe[3] as i32 - 136);
+        Vector3::new(rgbe[0] as f32 + 0.5, rgbe[1] as f32 + 0.5, rgbe[2] as f32 + 0.5) * scale
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_byte(reader: &mut impl BufRead) -> io::Result<u8> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Decodes one run length encoded component of a scanline, the inverse of `write_component`.
+fn read_component(reader: &mut impl BufRead, data: &mut [u8]) -> io::Result<()> {
+    let mut i = 0;
+    while i < data.len() {
+        let count = read_byte(reader)? as usize;
+        if count > 128 {
+            let end = i + count - 128;
+            if end > data.len() {
+                return Err(invalid("hdr run overflows its scanline"))
+            }
+            let byte = read_byte(reader)?;
+            data[i..end].iter_mut().for_each(|b| *b = byte);
+            i = end;
+        } else {
+            let end = i + count;
+            if count == 0 || end > data.len() {
+                return Err(invalid("invalid hdr run"))
+            }
+            reader.read_exact(&mut data[i..end])?;
+            i = end;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a Radiance `.hdr` image stored from the top row, with flat or run length encoded scanlines.
+pub fn read(reader: &mut impl BufRead) -> io::Result<(usize, usize, Vec<Vector3<f32>>)> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("not a radiance hdr file"))
+    }
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("truncated hdr header"))
+        }
+        match line.trim() {
+            "" => break,
+            format if format.starts_with("FORMAT=") && format != "FORMAT=32-bit_rle_rgbe" =>
+                return Err(invalid("unsupported hdr format, expected 32-bit_rle_rgbe")),
+            _ => {}
+        }
+    }
+    line.clear();
+    reader.read_line(&mut line)?;
+    let (height, width) = match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["-Y", height, "+X", width] => (height.parse::<usize>(), width.parse::<usize>()),
+        _ => return Err(invalid("unsupported hdr orientation, expected -Y +X"))
+    };
+    let (height, width) = (height.map_err(|_| invalid("invalid hdr size"))?, width.map_err(|_| invalid("invalid hdr size"))?);
+    let pixel_count = width.checked_mul(height).ok_or_else(|| invalid("invalid hdr size"))?;
+    let scanline_size = width.checked_mul(4).ok_or_else(|| invalid("invalid hdr size"))?;
+    let mut pixels = Vec::with_capacity(pixel_count);
+    let mut rgbe = vec![0u8; scanline_size];
+    let mut components = vec![0u8; width];
+    for _ in 0..height {
+        reader.read_exact(&mut rgbe[..4])?;
+        if (8..0x8000).contains(&width) && rgbe[0] == 2 && rgbe[1] == 2 && rgbe[2] < 128 {
+            if ((rgbe[2] as usize) << 8 | rgbe[3] as usize) != width {
+                return Err(invalid("hdr scanline width mismatch"))
+            }
+            for c in 0..4 {
+                read_component(reader, &mut components)?;
+                for (x, &b) in components.iter().enumerate() {
+                    rgbe[4 * x + c] = b;
+                }
+            }
+        } else {
+            reader.read_exact(&mut rgbe[4..])?;
+        }
+        pixels.extend(rgbe.chunks(4).map(from_rgbe));
+    }
+    Ok((width, height, pixels))
+}
+
+/// Run length encodes one component of a scanline: runs of equal bytes
+/// are stored as `128 + count, byte`, everything else as `count, bytes...`.
+fn write_component(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..].iter().take(MAX_RUN).take_while(|&&b| b == data[i]).count();
+        if run >= MIN_RUN {
+            writer.write_all(&[128 + run as u8, data[i]])?;
+            i += run;
+        } else {
+            let mut end = i;
+            while end < data.len() && end - i < MAX_RUN {
+                let ahead = data[end..].iter().take(MIN_RUN).take_while(|&&b| b == data[end]).count();
+                if ahead >= MIN_RUN {
+                    break
+                }
+                end += 1;
+            }
+            writer.write_all(&[(end - i) as u8])?;
+            writer.write_all(&data[i..end])?;
+            i = end;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a Radiance `.hdr` image, with run length encoded scanlines when the width allows it.
+pub fn write(writer: &mut impl Write, width: usize, height: usize, pixels: &[Vector3<f32>]) -> io::Result<()> {
+    write!(writer, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)?;
+    for row in pixels.chunks(width) {
+        let rgbe = row.iter().map(to_rgbe).collect::<Vec<[u8; 4]>>();
+        if (8..0x8000).contains(&width) {
+            writer.write_all(&[2, 2, (width >> 8) as u8, width as u8])?;
+            for c in 0..4 {
+                write_component(writer, &rgbe.iter().map(|p| p[c]).collect::<Vec<u8>>())?;
+            }
+        } else {
+            for p in rgbe {
+                writer.write_all(&p)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let pixels = (0..20).map(|x| Vector3::new(x as f32, 1.0, 0.0)).collect::<Vec<Vector3<f32>>>();
+        let mut data = Vec::new();
+        write(&mut data, 10, 2, &pixels).unwrap();
+        let (width, height, read_pixels) = read(&mut data.as_slice()).unwrap();
+        assert_eq!((width, height), (10, 2));
+        for (a, b) in pixels.iter().zip(read_pixels.iter()) {
+            assert!((a - b).norm() <= 0.01 * a.norm().max(0.01));
+        }
+    }
+
+    #[test]
+    fn oversized_header_is_invalid() {
+        let header = format!("#?RADIANCE\n\n-Y {} +X {}\n", usize::MAX / 2, 3);
+        assert_eq!(read(&mut header.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
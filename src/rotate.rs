@@ -61,30 +61,43 @@ impl<H: Hitable> Rotate<H> {
         });
         Rotate { axis, sin_theta, cos_theta, hitable, bbox }
     }
+
+    /// Vector in the frame of the wrapped hitable.
+    fn to_object(&self, v: &Vector3<f32>) -> Vector3<f32> {
+        let (_, a_axis, b_axis) = get_axis(&self.axis);
+        let mut r = *v;
+        r[a_axis] = self.cos_theta * v[a_axis] + self.sin_theta * v[b_axis];
+        r[b_axis] = -self.sin_theta * v[a_axis] + self.cos_theta * v[b_axis];
+        r
+    }
+
+    /// Vector of the frame of the wrapped hitable back in the world.
+    fn to_world(&self, v: &Vector3<f32>) -> Vector3<f32> {
+        let (_, a_axis, b_axis) = get_axis(&self.axis);
+        let mut r = *v;
+        r[a_axis] = self.cos_theta * v[a_axis] - self.sin_theta * v[b_axis];
+        r[b_axis] = self.sin_theta * v[a_axis] + self.cos_theta * v[b_axis];
+        r
+    }
 }
 
 impl<H: Hitable> Hitable for Rotate<H> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let (_, a_axis, b_axis) = get_axis(&self.axis);
-        let mut origin = ray.origin();
-        let mut direction = ray.direction();
-        origin[a_axis] = self.cos_theta * ray.origin()[a_axis] + self.sin_theta * ray.origin()[b_axis];
-        origin[b_axis] = -self.sin_theta * ray.origin()[a_axis] + self.cos_theta * ray.origin()[b_axis];
-        direction[a_axis] = self.cos_theta * ray.direction()[a_axis] + self.sin_theta * ray.direction()[b_axis];
-        direction[b_axis] = -self.sin_theta * ray.direction()[a_axis] + self.cos_theta * ray.direction()[b_axis];
-        let rotated_ray = Ray::new(origin, direction, ray.time());
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let rotated_ray = Ray::new(self.to_object(&ray.origin()), self.to_object(&ray.direction()), ray.time());
         self.hitable.hit(&rotated_ray, t_min, t_max).map(|mut hit| {
-            let mut p = hit.p;
-            let mut normal = hit.normal;
-            p[a_axis] = self.cos_theta * hit.p[a_axis] - self.sin_theta * hit.p[b_axis];
-            p[b_axis] = self.sin_theta * hit.p[a_axis] + self.cos_theta * hit.p[b_axis];
-            normal[a_axis] = self.cos_theta * hit.normal[a_axis] - self.sin_theta * hit.normal[b_axis];
-            normal[b_axis] = self.sin_theta * hit.normal[a_axis] + self.cos_theta * hit.normal[b_axis];
-            hit.p = p;
-            hit.normal = normal;
+            hit.p = self.to_world(&hit.p);
+            hit.normal = self.to_world(&hit.normal);
             hit
         })
     }
 
-    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> { self.bbox.clone() }
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> { self.bbox }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
+        self.hitable.pdf_value(self.to_object(&o), self.to_object(&v))
+    }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> { self.to_world(&self.hitable.random(self.to_object(&o))) }
+
+    fn power(&self) -> f32 { self.hitable.power() }
 }
\ No newline at end of file
@@ -0,0 +1,774 @@
+use std::f32;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use nalgebra::{Unit, UnitQuaternion, Vector2, Vector3};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::texture::{
+    Texture, ConstantTexture, ImageTexture, Filter, Wrap,
+    CheckerTexture, UvCheckerTexture, NoiseTexture, MarbleTexture, WoodTexture};
+use crate::perlin::Perlin;
+use crate::material::{Material, Lambertian, Metal, Conductor, METALS, Ior, GLASSES, Dielectric, RoughDielectric, DiffuseLight, Isotropic};
+use crate::hitable::{Hitable, HitableList, FlipNormals};
+use crate::sphere::{Sphere, MovingSphere};
+use crate::rect::{AARect, Plane};
+use crate::cube::Cube;
+use crate::triangle::{Triangle, TriangleMesh, Vertex, VertexBuffers};
+use crate::translate::Translate;
+use crate::rotate::{Rotate, Axis};
+use crate::motion::{Animated, Keyframe};
+use crate::medium::{ConstantMedium, GridMedium};
+use crate::grid::DensityGrid;
+use crate::camera::Camera;
+use crate::bvh::BvhNode;
+use crate::pdf::PDF;
+use crate::distribution::Distribution1D;
+use crate::light::DeltaLight;
+use crate::environment::{Environment, ConstantEnvironment, GradientEnvironment, EnvironmentMap, Sky};
+use crate::image::{Image, Framebuffer};
+use crate::random;
+use crate::spectrum;
+use crate::obj;
+
+/// Shape sampled when scattering, chosen among the lights in proportion to its weight.
+pub struct Light {
+    pub shape: Arc<dyn Hitable>,
+    pub weight: f32
+}
+
+impl Light {
+    /// Light weighted by the power it emits.
+    pub fn new(shape: Arc<dyn Hitable>) -> Self {
+        let weight = shape.power();
+        Light { shape, weight }
+    }
+}
+
+/// Kind of light chosen to be sampled from a point.
+pub enum LightSample<'a> {
+    /// Density of the directions towards the shapes sampled as lights and the environment.
+    Area(PDF<'a>),
+    Delta(&'a DeltaLight)
+}
+
+/// Everything needed to render an image.
+pub struct Scene {
+    pub world: Box<dyn Hitable>,
+    /// Hitables of the world that emit light, then the shapes sampled on purpose.
+    pub lights: Vec<Light>,
+    /// Light from the directions that leave the world, sampled after the shapes.
+    pub environment: Option<Box<dyn Environment>>,
+    pub delta_lights: Vec<DeltaLight>,
+    pub camera: Camera,
+    light_weights: Distribution1D,
+    delta_weights: Distribution1D,
+    /// Probability of sampling the shapes or the environment rather than the delta lights, by their power.
+    area_probability: f32
+}
+
+impl Scene {
+    /// Builds a bvh over the world, for the time the camera shutter is open, and samples the hitables
+    /// of the world that emit light along with `lights`. Lights that emit nothing, such as glass sampled
+    /// for the caustics it focuses, are given the mean weight of the others, so that they still get
+    /// their share of samples. The environment is sampled after them, by the power it sends into the scene.
+    pub fn new(
+        world: HitableList,
+        lights: Vec<Light>,
+        delta_lights: Vec<DeltaLight>,
+        environment: Option<Box<dyn Environment>>,
+        camera: Camera
+    ) -> Self {
+        let lights = world.emitters().into_iter().map(Light::new).chain(lights).collect::<Vec<Light>>();
+        let (time0, time1) = camera.shutter();
+        let world = Box::new(BvhNode::new(world, time0, time1));
+        let radius = world.bounding_box(time0, time1).map_or(0.0, |b| 0.5 * (b.max - b.min).norm());
+        // the environment lights the scene through a sphere around it, like area lights facing inwards
+        let environment_power = environment.iter()
+            .map(|environment| 4.0 * f32::consts::PI * radius.powi(2) * environment.luminance());
+        let powers = lights.iter().map(|light| light.weight).chain(environment_power).collect::<Vec<f32>>();
+        let emitting = powers.iter().copied().filter(|&weight| weight > 0.0).collect::<Vec<f32>>();
+        let mean = if emitting.is_empty() { 1.0 } else { emitting.iter().sum::<f32>() / emitting.len() as f32 };
+        let weights = powers.iter()
+            .map(|&weight| if weight > 0.0 { weight } else { mean })
+            .collect::<Vec<f32>>();
+        let light_weights = Distribution1D::new(&weights);
+        let delta_powers = delta_lights.iter().map(|light| light.power(radius)).collect::<Vec<f32>>();
+        let delta_weights = Distribution1D::new(&delta_powers);
+        let area_power: f32 = emitting.iter().sum();
+        let delta_power: f32 = delta_powers.iter().sum();
+        let area_probability = if delta_lights.is_empty() {
+            1.0
+        } else if weights.is_empty() {
+            0.0
+        } else if area_power + delta_power > 0.0 {
+            area_power / (area_power + delta_power)
+        } else {
+            0.5
+        };
+        Scene { world, lights, environment, delta_lights, camera, light_weights, delta_weights, area_probability }
+    }
+
+    /// Radiance of the environment seen by a ray leaving the world along `direction`.
+    pub fn background(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        self.environment.as_ref().map_or(Vector3::zeros(), |environment| environment.radiance(&direction))
+    }
+
+    /// Density of the directions towards the shapes sampled as lights and the environment from `origin`,
+    /// none when there are only delta lights.
+    pub fn light_pdf(&self, origin: Vector3<f32>) -> Option<PDF<'_>> {
+        if self.light_weights.len() == 0 {
+            return None
+        }
+        let components = self.lights.iter().map(|light| PDF::hitable(light.shape.as_ref(), origin))
+            .chain(self.environment.iter().map(|environment| PDF::environment(environment.as_ref())))
+            .collect();
+        Some(PDF::mixture(components, &self.light_weights))
+    }
+
+    /// Chooses the lights to sample from `origin`, with the probability of that choice.
+    pub fn sample_light(&self, origin: Vector3<f32>) -> Option<(LightSample<'_>, f32)> {
+        let u = random::rng().gen::<f32>();
+        if u < self.area_probability {
+            self.light_pdf(origin).map(|lights| (LightSample::Area(lights), self.area_probability))
+        } else if !self.delta_lights.is_empty() {
+            let i = self.delta_weights.sample((u - self.area_probability) / (1.0 - self.area_probability));
+            Some((LightSample::Delta(&self.delta_lights[i]), (1.0 - self.area_probability) * self.delta_weights.pmf(i)))
+        } else {
+            None
+        }
+    }
+
+    /// Density of sampling `direction` from `origin` towards the shapes sampled as lights and the environment,
+    /// including the probability of choosing them over the delta lights.
+    pub fn light_pdf_value(&self, origin: Vector3<f32>, direction: Vector3<f32>) -> f32 {
+        self.light_pdf(origin).map_or(0.0, |lights| self.area_probability * lights.value(direction))
+    }
+}
+
+/// Tokens of a single line of a scene file.
+struct Tokens<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize
+}
+
+impl<'a> Tokens<'a> {
+    fn new(line: &'a str) -> Self {
+        let line = line.split('#').next().unwrap_or("");
+        Tokens { tokens: line.split_whitespace().collect(), pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool { self.tokens.is_empty() }
+
+    fn peek(&self) -> Option<&'a str> { self.tokens.get(self.pos).copied() }
+
+    fn next(&mut self, what: &str) -> Result<&'a str, String> {
+        let token = self.peek().ok_or_else(|| format!("expected {}", what))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn number(&mut self, what: &str) -> Result<f32, String> {
+        let token = self.next(what)?;
+        token.parse::<f32>().map_err(|_| format!("expected {}, found '{}'", what, token))
+    }
+
+    /// Number in [0, 1], from perfectly smooth to fully rough.
+    fn roughness(&mut self, what: &str) -> Result<f32, String> {
+        let roughness = self.number(what)?;
+        if !(0.0..=1.0).contains(&roughness) {
+            return Err(format!("{} must be between 0 and 1, found '{}'", what, roughness))
+        }
+        Ok(roughness)
+    }
+
+    fn vector(&mut self, what: &str) -> Result<Vector3<f32>, String> {
+        Ok(Vector3::new(self.number(what)?, self.number(what)?, self.number(what)?))
+    }
+
+    fn integer(&mut self, what: &str) -> Result<u64, String> {
+        let token = self.next(what)?;
+        token.parse::<u64>().map_err(|_| format!("expected {}, found '{}'", what, token))
+    }
+
+    fn index(&mut self, what: &str) -> Result<usize, String> {
+        let token = self.next(what)?;
+        match token.parse::<usize>() {
+            Ok(index) if index > 0 => Ok(index - 1),
+            _ => Err(format!("expected {}, found '{}'", what, token))
+        }
+    }
+
+    /// Consumes `keyword` if it is the next token.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek() == Some(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn end(&self) -> Result<(), String> {
+        match self.peek() {
+            Some(token) => Err(format!("unexpected '{}'", token)),
+            None => Ok(())
+        }
+    }
+}
+
+struct CameraSettings {
+    look_from: Option<Vector3<f32>>,
+    look_at: Option<Vector3<f32>>,
+    view_up: Vector3<f32>,
+    vertical_fov: f32,
+    aperture: f32,
+    focus_dist: Option<f32>,
+    time0: f32,
+    time1: f32
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            look_from: None,
+            look_at: None,
+            view_up: Vector3::new(0.0, 1.0, 0.0),
+            vertical_fov: 40.0,
+            aperture: 0.0,
+            focus_dist: None,
+            time0: 0.0,
+            time1: 1.0
+        }
+    }
+}
+
+#[derive(Default)]
+struct Loader {
+    /// Directory that relative file names are resolved against.
+    dir: PathBuf,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    shapes: HashMap<String, Arc<dyn Hitable>>,
+    world: HitableList,
+    lights: Vec<Light>,
+    delta_lights: Vec<DeltaLight>,
+    environment: Option<Box<dyn Environment>>,
+    camera: Option<CameraSettings>
+}
+
+impl Loader {
+    fn define<T>(map: &mut HashMap<String, T>, kind: &str, name: &str, value: T) -> Result<(), String> {
+        if map.insert(name.to_string(), value).is_some() {
+            Err(format!("{} '{}' is already defined", kind, name))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// A texture is either the name of a defined texture or an inline constant color.
+    fn texture(&self, tokens: &mut Tokens) -> Result<Arc<dyn Texture>, String> {
+        match tokens.peek() {
+            Some(token) if token.parse::<f32>().is_ok() => {
+                let color = tokens.vector("color")?;
+                Ok(Arc::new(ConstantTexture::new(color.x, color.y, color.z)))
+            },
+            _ => {
+                let name = tokens.next("texture")?;
+                self.textures.get(name).cloned().ok_or_else(|| format!("unknown texture '{}'", name))
+            }
+        }
+    }
+
+    /// Noise of procedural textures, from the optional `seed n` at the end of the line.
+    fn perlin(&self, tokens: &mut Tokens) -> Result<Perlin, String> {
+        let seed = if tokens.keyword("seed") { tokens.integer("seed")? } else { 0 };
+        Ok(Perlin::new(&mut StdRng::seed_from_u64(seed)))
+    }
+
+    fn new_texture(&self, tokens: &mut Tokens) -> Result<Arc<dyn Texture>, String> {
+        match tokens.next("texture type")? {
+            "checker" => {
+                let size = tokens.number("size")?;
+                Ok(Arc::new(CheckerTexture::new(size, self.texture(tokens)?, self.texture(tokens)?)))
+            },
+            "uv_checker" => {
+                let nu = tokens.number("nu")?;
+                let nv = tokens.number("nv")?;
+                Ok(Arc::new(UvCheckerTexture::new(nu, nv, self.texture(tokens)?, self.texture(tokens)?)))
+            },
+            "noise" => {
+                let scale = tokens.number("scale")?;
+                Ok(Arc::new(NoiseTexture::new(self.perlin(tokens)?, scale)))
+            },
+            "marble" => {
+                let scale = tokens.number("scale")?;
+                let color = tokens.vector("color")?;
+                Ok(Arc::new(MarbleTexture::new(self.perlin(tokens)?, scale, color)))
+            },
+            "wood" => {
+                let scale = tokens.number("scale")?;
+                let light = tokens.vector("light color")?;
+                let dark = tokens.vector("dark color")?;
+                Ok(Arc::new(WoodTexture::new(self.perlin(tokens)?, scale, light, dark)))
+            },
+            "constant" => {
+                let color = tokens.vector("color")?;
+                Ok(Arc::new(ConstantTexture::new(color.x, color.y, color.z)))
+            },
+            "image" => {
+                let path = self.dir.join(tokens.next("file name")?);
+                let image = Image::open(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+                let mut filter = Filter::Bilinear;
+                let mut wrap = Wrap::Repeat;
+                while let Some(option) = tokens.peek() {
+                    match option {
+                        "nearest" => filter = Filter::Nearest,
+                        "bilinear" => filter = Filter::Bilinear,
+                        "repeat" => wrap = Wrap::Repeat,
+                        "clamp" => wrap = Wrap::Clamp,
+                        "mirror" => wrap = Wrap::Mirror,
+                        option => return Err(format!("unknown image texture option '{}'", option))
+                    }
+                    tokens.next(option)?;
+                }
+                Ok(Arc::new(ImageTexture::new(&image, filter, wrap)))
+            },
+            kind => Err(format!("unknown texture type '{}'", kind))
+        }
+    }
+
+    fn material(&self, tokens: &mut Tokens) -> Result<Arc<dyn Material>, String> {
+        let name = tokens.next("material")?;
+        self.materials.get(name).cloned().ok_or_else(|| format!("unknown material '{}'", name))
+    }
+
+    /// Refractive index of dielectrics: a number, the coefficients of a dispersion formula, or a glass name.
+    fn ior(&self, tokens: &mut Tokens) -> Result<Ior, String> {
+        match tokens.next("refractive index")? {
+            "cauchy" => Ok(Ior::Cauchy { a: tokens.number("cauchy a")?, b: tokens.number("cauchy b")? }),
+            "sellmeier" => {
+                let b = tokens.vector("sellmeier b")?;
+                let c = tokens.vector("sellmeier c")?;
+                let ior = Ior::Sellmeier { b: b.into(), c: c.into() };
+                // n² has a pole at each c in squared micrometers, and the index is NaN where n² is negative
+                let visible = (spectrum::MIN_WAVELENGTH / 1000.0).powi(2)..=(spectrum::MAX_WAVELENGTH / 1000.0).powi(2);
+                let wavelengths = spectrum::MIN_WAVELENGTH as u32..=spectrum::MAX_WAVELENGTH as u32;
+                if c.iter().any(|c| visible.contains(c)) || !wavelengths.into_iter().all(|l| ior.at(Some(l as f32)) > 0.0) {
+                    return Err(String::from("sellmeier coefficients must give n² > 0 over the visible wavelengths"))
+                }
+                Ok(ior)
+            },
+            token => match token.parse::<f32>() {
+                Ok(n) => Ok(Ior::Constant(n)),
+                Err(_) => GLASSES.iter().find(|(glass, _)| *glass == token).map(|(_, ior)| *ior).ok_or_else(||
+                    format!("unknown refractive index '{}', expected a number, cauchy, sellmeier, bk7, fused_silica or diamond", token))
+            }
+        }
+    }
+
+    /// Absorption coefficients of dielectrics, from the optional `absorption r g b` at the end of the line.
+    fn absorption(&self, tokens: &mut Tokens) -> Result<Vector3<f32>, String> {
+        if tokens.keyword("absorption") {
+            let absorption = tokens.vector("absorption")?;
+            if !absorption.iter().all(|&a| a >= 0.0) {
+                return Err(String::from("absorption must not be negative"))
+            }
+            Ok(absorption)
+        } else {
+            Ok(Vector3::zeros())
+        }
+    }
+
+    fn new_material(&self, tokens: &mut Tokens) -> Result<Arc<dyn Material>, String> {
+        match tokens.next("material type")? {
+            "lambertian" => Ok(Arc::new(Lambertian::new(self.texture(tokens)?))),
+            "metal" => Ok(Arc::new(Metal::new(tokens.vector("albedo")?, tokens.number("fuzz")?))),
+            "conductor" => {
+                let (eta, k) = match tokens.peek() {
+                    Some(token) if token.parse::<f32>().is_ok() => (tokens.vector("eta")?, tokens.vector("k")?),
+                    _ => {
+                        let name = tokens.next("metal")?;
+                        let (_, eta, k) = METALS.iter().find(|(metal, _, _)| *metal == name)
+                            .ok_or_else(|| format!("unknown metal '{}', expected gold, silver, copper, aluminum or eta and k", name))?;
+                        (Vector3::from(*eta), Vector3::from(*k))
+                    }
+                };
+                let roughness = tokens.roughness("roughness")?;
+                if tokens.keyword("anisotropic") {
+                    let roughness_v = tokens.roughness("roughness across the axis")?;
+                    Ok(Arc::new(Conductor::anisotropic(eta, k, roughness, roughness_v, tokens.vector("axis")?)))
+                } else {
+                    Ok(Arc::new(Conductor::new(eta, k, roughness)))
+                }
+            },
+            "dielectric" => {
+                let ior = self.ior(tokens)?;
+                Ok(Arc::new(Dielectric::absorbing(ior, self.absorption(tokens)?)))
+            },
+            "rough_dielectric" => {
+                let ior = self.ior(tokens)?;
+                Ok(Arc::new(RoughDielectric::new(ior, tokens.roughness("roughness")?, self.absorption(tokens)?)))
+            },
+            "diffuse_light" => Ok(Arc::new(DiffuseLight::new(self.texture(tokens)?))),
+            "isotropic" => Ok(Arc::new(Isotropic::new(self.texture(tokens)?))),
+            kind => Err(format!("unknown material type '{}'", kind))
+        }
+    }
+
+    /// A shape is either the name of a defined shape or an inline definition.
+    /// Transforms take the shape they apply to as their last argument, so they can be nested.
+    fn shape(&self, tokens: &mut Tokens) -> Result<Arc<dyn Hitable>, String> {
+        match tokens.next("shape")? {
+            "sphere" => {
+                let center = tokens.vector("center")?;
+                let radius = tokens.number("radius")?;
+                Ok(Arc::new(Sphere::new(center, radius, self.material(tokens)?)))
+            },
+            "moving_sphere" => {
+                let center0 = tokens.vector("center0")?;
+                let center1 = tokens.vector("center1")?;
+                let time0 = tokens.number("time0")?;
+                let time1 = tokens.number("time1")?;
+                let radius = tokens.number("radius")?;
+                Ok(Arc::new(MovingSphere::new(center0, center1, time0, time1, radius, self.material(tokens)?)?))
+            },
+            "rect" => {
+                let plane = match tokens.next("plane")? {
+                    "yz" => Plane::YZ,
+                    "zx" => Plane::ZX,
+                    "xy" => Plane::XY,
+                    plane => return Err(format!("unknown plane '{}', expected yz, zx or xy", plane))
+                };
+                let a0 = tokens.number("a0")?;
+                let a1 = tokens.number("a1")?;
+                let b0 = tokens.number("b0")?;
+                let b1 = tokens.number("b1")?;
+                let k = tokens.number("k")?;
+                Ok(Arc::new(AARect::new(plane, a0, a1, b0, b1, k, self.material(tokens)?)))
+            },
+            "cube" => {
+                let p_min = tokens.vector("minimum corner")?;
+                let p_max = tokens.vector("maximum corner")?;
+                Ok(Arc::new(Cube::new(p_min, p_max, self.material(tokens)?)))
+            },
+            "triangle" => {
+                let vertices = [tokens.vector("vertex")?, tokens.vector("vertex")?, tokens.vector("vertex")?];
+                let normals = if tokens.keyword("normals") {
+                    Some([tokens.vector("normal")?, tokens.vector("normal")?, tokens.vector("normal")?])
+                } else {
+                    None
+                };
+                let uvs = if tokens.keyword("uvs") {
+                    let mut uv = || Ok::<_, String>(Vector2::new(tokens.number("u")?, tokens.number("v")?));
+                    Some([uv()?, uv()?, uv()?])
+                } else {
+                    None
+                };
+                Ok(Arc::new(Triangle::new(vertices, normals, uvs, self.material(tokens)?)))
+            },
+            "mesh" => {
+                let mut buffers = VertexBuffers::default();
+                let mut faces = Vec::new();
+                loop {
+                    if tokens.keyword("v") {
+                        buffers.positions.push(tokens.vector("vertex")?);
+                    } else if tokens.keyword("f") {
+                        let mut vertex = || Ok::<_, String>(Vertex { position: tokens.index("vertex index")?, normal: None, uv: None });
+                        faces.push([vertex()?, vertex()?, vertex()?]);
+                    } else {
+                        break
+                    }
+                }
+                let material = self.material(tokens)?;
+                Ok(Arc::new(TriangleMesh::new(Arc::new(buffers), faces, material)?))
+            },
+            "obj" => {
+                let path = self.dir.join(tokens.next("file name")?);
+                Ok(Arc::new(BvhNode::new(obj::load(&path)?, 0.0, 1.0)))
+            },
+            "translate" => {
+                let offset = tokens.vector("offset")?;
+                Ok(Arc::new(Translate::new(self.shape(tokens)?, offset)))
+            },
+            "rotate" => {
+                let axis = match tokens.next("axis")? {
+                    "x" => Axis::X,
+                    "y" => Axis::Y,
+                    "z" => Axis::Z,
+                    axis => return Err(format!("unknown axis '{}', expected x, y or z", axis))
+                };
+                let angle = tokens.number("angle")?;
+                Ok(Arc::new(Rotate::new(axis, self.shape(tokens)?, angle)))
+            },
+            "animate" => {
+                let mut keyframes = Vec::new();
+                while tokens.keyword("key") {
+                    let time = tokens.number("time")?;
+                    let translation = tokens.vector("translation")?;
+                    let axis = tokens.vector("rotation axis")?;
+                    let angle = tokens.number("angle")?;
+                    let rotation = match Unit::try_new(axis, 1e-6) {
+                        Some(axis) => UnitQuaternion::from_axis_angle(&axis, angle.to_radians()),
+                        None if angle == 0.0 => UnitQuaternion::identity(),
+                        None => return Err(String::from("rotation axis is zero"))
+                    };
+                    keyframes.push(Keyframe { time, translation, rotation });
+                }
+                Ok(Arc::new(Animated::new(self.shape(tokens)?, keyframes)?))
+            },
+            "constant_medium" => {
+                let density = tokens.number("density")?;
+                let phase_function = self.material(tokens)?;
+                Ok(Arc::new(ConstantMedium::new(self.shape(tokens)?, density, phase_function)?))
+            },
+            "grid_medium" => {
+                let path = self.dir.join(tokens.next("file name")?);
+                let grid = DensityGrid::open(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+                let min = tokens.vector("minimum corner")?;
+                let max = tokens.vector("maximum corner")?;
+                let scale = tokens.number("density scale")?;
+                Ok(Arc::new(GridMedium::new(grid, min, max, scale, self.material(tokens)?)))
+            },
+            "flip" => Ok(Arc::new(FlipNormals::new(self.shape(tokens)?))),
+            name => self.shapes.get(name).cloned().ok_or_else(|| format!("unknown shape '{}'", name))
+        }
+    }
+
+    fn environment(&self, tokens: &mut Tokens) -> Result<Box<dyn Environment>, String> {
+        match tokens.next("environment type")? {
+            "constant" => Ok(Box::new(ConstantEnvironment::new(tokens.vector("color")?))),
+            "gradient" => Ok(Box::new(GradientEnvironment::new(tokens.vector("bottom color")?, tokens.vector("top color")?))),
+            "map" => {
+                let path = self.dir.join(tokens.next("file name")?);
+                let image = Framebuffer::open(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+                if image.width() == 0 || image.height() == 0 {
+                    return Err(format!("{}: empty environment map", path.display()))
+                }
+                let scale = if tokens.keyword("scale") { tokens.number("scale")? } else { 1.0 };
+                let rotation = if tokens.keyword("rotate") { tokens.number("angle")? } else { 0.0 };
+                Ok(Box::new(EnvironmentMap::new(image, scale, rotation)))
+            },
+            "sky" => {
+                let sun = tokens.vector("sun direction")?;
+                let turbidity = if tokens.keyword("turbidity") { tokens.number("turbidity")? } else { 3.0 };
+                let scale = if tokens.keyword("scale") { tokens.number("scale")? } else { 0.1 };
+                Ok(Box::new(Sky::new(sun, turbidity, scale)?))
+            },
+            kind => Err(format!("unknown environment type '{}', expected constant, gradient, map or sky", kind))
+        }
+    }
+
+    fn camera(&self, tokens: &mut Tokens) -> Result<CameraSettings, String> {
+        let mut camera = CameraSettings::default();
+        while let Some(key) = tokens.peek() {
+            tokens.next(key)?;
+            match key {
+                "look_from" => camera.look_from = Some(tokens.vector("look_from")?),
+                "look_at" => camera.look_at = Some(tokens.vector("look_at")?),
+                "up" => camera.view_up = tokens.vector("up")?,
+                "fov" => camera.vertical_fov = tokens.number("fov")?,
+                "aperture" => camera.aperture = tokens.number("aperture")?,
+                "focus_dist" => camera.focus_dist = Some(tokens.number("focus_dist")?),
+                "time" => {
+                    camera.time0 = tokens.number("time0")?;
+                    camera.time1 = tokens.number("time1")?;
+                },
+                key => return Err(format!("unknown camera setting '{}'", key))
+            }
+        }
+        Ok(camera)
+    }
+
+    fn statement(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        match tokens.next("statement")? {
+            "texture" => {
+                let name = tokens.next("texture name")?;
+                let texture = self.new_texture(tokens)?;
+                Loader::define(&mut self.textures, "texture", name, texture)
+            },
+            "material" => {
+                let name = tokens.next("material name")?;
+                let material = self.new_material(tokens)?;
+                Loader::define(&mut self.materials, "material", name, material)
+            },
+            "shape" => {
+                let name = tokens.next("shape name")?;
+                let shape = self.shape(tokens)?;
+                Loader::define(&mut self.shapes, "shape", name, shape)
+            },
+            "add" => {
+                let shape = self.shape(tokens)?;
+                self.world.push(shape);
+                Ok(())
+            },
+            "light" => {
+                let weight = if tokens.keyword("weight") { Some(tokens.number("weight")?) } else { None };
+                if weight.is_some_and(|weight| weight.is_nan() || weight <= 0.0) {
+                    return Err(String::from("light weight must be positive"))
+                }
+                let shape = self.shape(tokens)?;
+                self.lights.push(match weight {
+                    Some(weight) => Light { shape, weight },
+                    None => Light::new(shape)
+                });
+                Ok(())
+            },
+            "point_light" => {
+                let light = DeltaLight::point(tokens.vector("position")?, tokens.vector("intensity")?);
+                self.delta_lights.push(light);
+                Ok(())
+            },
+            "spot_light" => {
+                let position = tokens.vector("position")?;
+                let target = tokens.vector("target")?;
+                let cone = tokens.number("cone angle")?;
+                let falloff_start = tokens.number("falloff start angle")?;
+                let light = DeltaLight::spot(position, target, tokens.vector("intensity")?, cone, falloff_start)?;
+                self.delta_lights.push(light);
+                Ok(())
+            },
+            "directional_light" => {
+                let light = DeltaLight::directional(tokens.vector("direction")?, tokens.vector("irradiance")?)?;
+                self.delta_lights.push(light);
+                Ok(())
+            },
+            "environment" => {
+                if self.environment.is_some() {
+                    return Err(String::from("environment is already defined"))
+                }
+                self.environment = Some(self.environment(tokens)?);
+                Ok(())
+            },
+            "camera" => {
+                if self.camera.is_some() {
+                    return Err(String::from("camera is already defined"))
+                }
+                self.camera = Some(self.camera(tokens)?);
+                Ok(())
+            },
+            statement => Err(format!("unknown statement '{}'", statement))
+        }?;
+        tokens.end()
+    }
+
+    fn finish(self, aspect: f32) -> Result<Scene, String> {
+        let camera = self.camera.ok_or("missing camera")?;
+        let look_from = camera.look_from.ok_or("missing camera look_from")?;
+        let look_at = camera.look_at.ok_or("missing camera look_at")?;
+        let focus_dist = camera.focus_dist.unwrap_or_else(|| (look_from - look_at).norm());
+        let cam = Camera::new(
+            look_from, look_at, camera.view_up,
+            camera.vertical_fov, aspect, camera.aperture, focus_dist, camera.time0, camera.time1);
+        let scene = Scene::new(self.world, self.lights, self.delta_lights, self.environment, cam);
+        if scene.lights.is_empty() && scene.environment.is_none() && scene.delta_lights.is_empty() {
+            return Err(String::from("missing light, no shape emits light or is sampled and no light or environment is defined"))
+        }
+        Ok(scene)
+    }
+}
+
+/// Parses a scene description, with file names relative to `dir`.
+/// Errors are prefixed by the line they refer to.
+pub fn parse(source: &str, dir: &Path, aspect: f32) -> Result<Scene, String> {
+    let mut loader = Loader { dir: dir.to_path_buf(), ..Loader::default() };
+    for (index, line) in source.lines().enumerate() {
+        let mut tokens = Tokens::new(line);
+        if !tokens.is_empty() {
+            loader.statement(&mut tokens).map_err(|err| format!("line {}: {}", index + 1, err))?;
+        }
+    }
+    loader.finish(aspect)
+}
+
+pub fn load(path: &Path, aspect: f32) -> Result<Scene, String> {
+    let source = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse(&source, dir, aspect).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    const SPHERE: &str = "\
+camera look_from 0 0 -10 look_at 0 0 0
+material white lambertian 0.5 0.5 0.5
+shape ball sphere 0 0 0 1 white
+add ball
+light ball";
+
+    fn error(source: &str) -> String {
+        match parse(source, Path::new(""), 1.0) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err
+        }
+    }
+
+    #[test]
+    fn shapes_are_added() {
+        let scene = parse(SPHERE, Path::new(""), 1.0).unwrap();
+        let ray = Ray::new(Vector3::new(0.0, 0.0, -10.0), Vector3::new(0.0, 0.0, 1.0), 0.0);
+        assert!((scene.world.hit(&ray, 0.001, f32::MAX).unwrap().t - 9.0).abs() < 1e-4);
+        assert!(scene.lights[0].shape.hit(&ray, 0.001, f32::MAX).is_some());
+        let scene = parse(&SPHERE.replace("light ball", "light weight 2.5 ball"), Path::new(""), 1.0).unwrap();
+        assert_eq!(scene.lights[0].weight, 2.5);
+    }
+
+    #[test]
+    fn lights_are_collected_from_the_world() {
+        let source = format!("{}\nmaterial lamp diffuse_light 4 4 4\nadd sphere 0 5 0 1 lamp", SPHERE);
+        let scene = parse(&source, Path::new(""), 1.0).unwrap();
+        assert_eq!(scene.lights.len(), 2);
+        assert!(scene.lights[0].weight > 0.0);
+        // the ball emits nothing, but is sampled as much as the lamp
+        assert_eq!(scene.lights[1].weight, 0.0);
+        assert_eq!((scene.light_weights.pmf(0), scene.light_weights.pmf(1)), (0.5, 0.5));
+    }
+
+    #[test]
+    fn delta_lights() {
+        let source = SPHERE.replace("light ball", "point_light 0 5 0 10 10 10\nspot_light 0 5 0 0 0 0 30 20 5 5 5");
+        let scene = parse(&source, Path::new(""), 1.0).unwrap();
+        assert!(scene.lights.is_empty());
+        assert_eq!(scene.delta_lights.len(), 2);
+        assert_eq!(scene.area_probability, 0.0);
+    }
+
+    #[test]
+    fn bundled_scene() {
+        parse(include_str!("../scenes/cornell_box.scene"), Path::new("scenes"), 1.0).unwrap();
+        parse(include_str!("../scenes/sky.scene"), Path::new("scenes"), 1.0).unwrap();
+        parse(include_str!("../scenes/dispersion.scene"), Path::new("scenes"), 1.0).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error(&format!("{}\nadd cone 0 0 0 1 white", SPHERE)), "line 6: unknown shape 'cone'");
+        assert_eq!(error(&format!("{}\nmaterial white metal 1 1 1 0", SPHERE)), "line 6: material 'white' is already defined");
+        assert_eq!(error("# comment\n\nadd sphere 0 0 0 1 black"), "line 3: unknown material 'black'");
+        assert_eq!(error("add sphere 0 0 zero 1 white"), "line 1: expected center, found 'zero'");
+        assert_eq!(error(&format!("{} 2", SPHERE)), "line 5: unexpected '2'");
+        assert_eq!(error(&SPHERE.replace("look_at 0 0 0", "")), "missing camera look_at");
+        assert_eq!(error(&SPHERE.replace("light ball", "")), "missing light, no shape emits light or is sampled and no light or environment is defined");
+        assert_eq!(error(&SPHERE.replace("light ball", "light weight 0 ball")), "line 5: light weight must be positive");
+        assert_eq!(error(&SPHERE.replace("light ball", "spot_light 0 5 0 0 0 0 30 40 1 1 1")),
+            "line 5: spot light angles must satisfy 0 <= falloff <= cone <= 180");
+        assert_eq!(error(&SPHERE.replace("light ball", "spot_light 0 5 0 0 5 0 30 20 1 1 1")),
+            "line 5: spot light target must differ from its position");
+        assert_eq!(error(&SPHERE.replace("light ball", "directional_light 0 0 0 1 1 1")),
+            "line 5: directional light direction must not be zero");
+        assert_eq!(error("material rough conductor gold 1.5"), "line 1: roughness must be between 0 and 1, found '1.5'");
+        assert_eq!(error("material rough conductor gold 0.5 anisotropic -0.1 1 0 0"),
+            "line 1: roughness across the axis must be between 0 and 1, found '-0.1'");
+        assert_eq!(error("material glass dielectric 1.5 absorption 0 -1 0"), "line 1: absorption must not be negative");
+        assert_eq!(error("material glass dielectric sellmeier 1 0 0 0.3 0 0"),
+            "line 1: sellmeier coefficients must give n² > 0 over the visible wavelengths");
+        assert_eq!(error("material glass dielectric sellmeier -2 0 0 0.01 0 0"),
+            "line 1: sellmeier coefficients must give n² > 0 over the visible wavelengths");
+        assert_eq!(error("material glass dielectric crown"),
+            "line 1: unknown refractive index 'crown', expected a number, cauchy, sellmeier, bk7, fused_silica or diamond");
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use crate::tonemap::ToneMapper;
+use crate::integrator::Heuristic;
+
+pub const USAGE: &str = "\
+Usage: ray-tracing-the-rest-of-your-life [OPTIONS]
+
+Options:
+  -W, --width <PIXELS>       image width [default: 500]
+  -H, --height <PIXELS>      image height [default: 500]
+  -s, --samples <N>          samples per pixel [default: 1000]
+  -d, --max-depth <N>        maximum number of bounces per path [default: 50]
+      --roulette-depth <N>   bounces before paths can be ended by russian roulette,
+                             which is disabled from the maximum depth [default: 5]
+      --mis <HEURISTIC>      weighting of light and material samples: balance or power
+                             [default: power]
+      --light-sampling <FRACTION>
+                             fraction of the bounces going on towards the lights
+                             instead of following the material, below 1 [default: 0.5]
+  -o, --output <FILE>        output image, .ppm, .png, .pfm, .hdr or .exr
+                             [default: ppm to standard output]
+      --scene <NAME|FILE>    built-in scene or scene file to render [default: cornell_box]
+  -j, --threads <N>          number of render threads [default: number of cpus]
+      --tonemap <OPERATOR>   tone mapping for 8 bit images: clamp, reinhard,
+                             extended-reinhard or aces [default: clamp]
+      --exposure <STOPS>     exposure adjustment before tone mapping [default: 0]
+      --white <RADIANCE>     radiance mapped to white by extended-reinhard [default: 1]
+      --seed <N>             seed for the random number generator [default: random]
+  -h, --help                 print this help";
+
+pub struct Settings {
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    pub max_depth: u32,
+    pub roulette_depth: u32,
+    pub heuristic: Heuristic,
+    pub light_sampling: f32,
+    pub output: Option<PathBuf>,
+    pub scene: String,
+    pub threads: Option<usize>,
+    pub seed: Option<u64>,
+    pub tone_mapper: ToneMapper
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            width: 500,
+            height: 500,
+            samples: 1000,
+            max_depth: 50,
+            roulette_depth: 5,
+            heuristic: Heuristic::Power,
+            light_sampling: 0.5,
+            output: None,
+            scene: String::from("cornell_box"),
+            threads: None,
+            seed: None,
+            tone_mapper: ToneMapper::default()
+        }
+    }
+}
+
+fn parse<T: FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{}'", option))?;
+    value.parse::<T>().map_err(|_| format!("invalid value '{}' for '{}'", value, option))
+}
+
+fn parse_positive<T: FromStr + PartialOrd + Default>(option: &str, value: Option<String>) -> Result<T, String> {
+    let n = parse::<T>(option, value)?;
+    if n > T::default() { Ok(n) } else { Err(format!("'{}' must be greater than zero", option)) }
+}
+
+impl Settings {
+    /// Parses command line arguments, without the program name.
+    /// Gives none when help is requested.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut settings = Settings::default();
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            let (option, inline_value) = match arg.split_once('=') {
+                Some((option, value)) if option.starts_with("--") => (option.to_string(), Some(value.to_string())),
+                _ => (arg, None)
+            };
+            let value = inline_value.or_else(|| if option == "-h" || option == "--help" { None } else { args.next() });
+            match option.as_str() {
+                "-W" | "--width" => settings.width = parse_positive(&option, value)?,
+                "-H" | "--height" => settings.height = parse_positive(&option, value)?,
+                "-s" | "--samples" => settings.samples = parse_positive(&option, value)?,
+                "-d" | "--max-depth" => settings.max_depth = parse(&option, value)?,
+                "--roulette-depth" => settings.roulette_depth = parse(&option, value)?,
+                "--mis" => settings.heuristic = parse(&option, value)?,
+                "--light-sampling" => {
+                    settings.light_sampling = parse(&option, value)?;
+                    if !(0.0..1.0).contains(&settings.light_sampling) {
+                        return Err(format!("'{}' must be at least 0 and below 1", option))
+                    }
+                },
+                "-o" | "--output" => settings.output = Some(parse(&option, value)?),
+                "--scene" => settings.scene = parse(&option, value)?,
+                "-j" | "--threads" => settings.threads = Some(parse_positive(&option, value)?),
+                "--seed" => settings.seed = Some(parse(&option, value)?),
+                "--tonemap" => settings.tone_mapper.operator = parse(&option, value)?,
+                "--exposure" => settings.tone_mapper.exposure = parse(&option, value)?,
+                "--white" => settings.tone_mapper.white = parse_positive(&option, value)?,
+                "-h" | "--help" => return Ok(None),
+                _ => return Err(format!("unknown option '{}'", option))
+            }
+        }
+        Ok(Some(settings))
+    }
+}
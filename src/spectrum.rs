@@ -0,0 +1,100 @@
+use std::sync::OnceLock;
+use nalgebra::Vector3;
+
+/// Range of visible wavelengths in nanometers, sampled uniformly.
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 780.0;
+
+/// Wavelength of the sodium D line, where refractive indices are usually quoted.
+pub const D_LINE: f32 = 589.3;
+
+/// Piecewise gaussian with different widths on each side of its peak.
+fn lobe(wavelength: f32, peak: f32, below: f32, above: f32) -> f32 {
+    let width = if wavelength < peak { below } else { above };
+    (-0.5 * ((wavelength - peak) / width).powi(2)).exp()
+}
+
+/// Linear sRGB of a single wavelength, from the fit of the CIE 1931 color matching functions of
+/// Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions".
+/// Spectral colors are outside of the sRGB gamut, negative components are clamped.
+fn rgb(wavelength: f32) -> Vector3<f32> {
+    let l = wavelength;
+    let x = 1.056 * lobe(l, 599.8, 37.9, 31.0) + 0.362 * lobe(l, 442.0, 16.0, 26.7) - 0.065 * lobe(l, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(l, 568.8, 46.9, 40.5) + 0.286 * lobe(l, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(l, 437.0, 11.8, 36.0) + 0.681 * lobe(l, 459.0, 26.0, 13.8);
+    Vector3::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z).map(|c| c.max(0.0))
+}
+
+/// Weights of the wavelengths every nanometer, scaled so that they average to white.
+fn weights() -> &'static [Vector3<f32>] {
+    static WEIGHTS: OnceLock<Vec<Vector3<f32>>> = OnceLock::new();
+    WEIGHTS.get_or_init(|| {
+        let count = (MAX_WAVELENGTH - MIN_WAVELENGTH) as usize + 1;
+        let colors = (0..count).map(|i| rgb(MIN_WAVELENGTH + i as f32)).collect::<Vec<Vector3<f32>>>();
+        let average = colors.iter().sum::<Vector3<f32>>() / count as f32;
+        colors.iter().map(|c| c.component_div(&average)).collect()
+    })
+}
+
+/// Number of wavelengths followed by a path through dispersive materials: a hero and its companions.
+pub const WAVELENGTHS: usize = 4;
+
+/// Hero wavelength uniformly distributed over the visible range, from `u` uniform in [0, 1), followed by
+/// companions evenly spaced after it, wrapping around the range, as in "Hero Wavelength Spectral Sampling"
+/// by Wilkie et al. Any of them could have been the hero of the same set, with the same probability.
+pub fn sample_wavelengths(u: f32) -> [f32; WAVELENGTHS] {
+    let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+    let mut wavelengths = [0.0; WAVELENGTHS];
+    for (i, wavelength) in wavelengths.iter_mut().enumerate() {
+        let offset = (u + i as f32 / WAVELENGTHS as f32).fract() * range;
+        *wavelength = MIN_WAVELENGTH + offset;
+    }
+    wavelengths
+}
+
+/// Color carried by light of a single `wavelength`: averaged over the visible range it is white,
+/// so the light of paths that keep only a few wavelengths has the right color on average.
+pub fn wavelength_weight(wavelength: f32) -> Vector3<f32> {
+    let weights = weights();
+    let x = (wavelength - MIN_WAVELENGTH).clamp(0.0, (weights.len() - 1) as f32);
+    let i = (x as usize).min(weights.len() - 2);
+    weights[i].lerp(&weights[i + 1], x - i as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn companions_are_evenly_spaced() {
+        let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+        for &u in &[0.0, 0.3, 0.9999] {
+            let wavelengths = sample_wavelengths(u);
+            assert_eq!(wavelengths[0], MIN_WAVELENGTH + u * range);
+            for (i, wavelength) in wavelengths.iter().enumerate() {
+                assert!((MIN_WAVELENGTH..MAX_WAVELENGTH).contains(wavelength));
+                let offset = (wavelength - wavelengths[0]).rem_euclid(range);
+                assert!((offset - i as f32 * range / WAVELENGTHS as f32).abs() < 1e-2);
+            }
+        }
+    }
+
+    #[test]
+    fn weights_average_to_white() {
+        let count = 10000;
+        let average = (0..count).map(|i| wavelength_weight(sample_wavelengths(i as f32 / count as f32)[0]))
+            .sum::<Vector3<f32>>() / count as f32;
+        assert!((average - Vector3::new(1.0, 1.0, 1.0)).amax() < 0.01, "{}", average);
+    }
+
+    #[test]
+    fn wavelengths_have_their_color() {
+        assert_eq!(wavelength_weight(450.0).imax(), 2);
+        assert_eq!(wavelength_weight(530.0).imax(), 1);
+        assert_eq!(wavelength_weight(650.0).imax(), 0);
+        assert!(wavelength_weight(MIN_WAVELENGTH - 10.0).iter().all(|c| c.is_finite()));
+    }
+}
@@ -2,9 +2,12 @@ use std::f32;
 use nalgebra::Vector3;
 use rand::Rng;
 use crate::ray::Ray;
+use crate::random;
 use crate::hitable::{Hitable, HitRecord};
 use crate::material::Material;
+use crate::aabb;
 use crate::aabb::AABB;
+use crate::color;
 use crate::onb::ONB;
 
 fn get_sphere_uv(p: &Vector3<f32>) -> (f32, f32) {
@@ -15,17 +18,75 @@ fn get_sphere_uv(p: &Vector3<f32>) -> (f32, f32) {
     (u, v)
 }
 
+/// Direction in the cone of a sphere at `distance_squared` from its center, along `z`. From inside
+/// or on the surface, the cone is the hemisphere towards the center.
 fn random_to_sphere(radius: f32, distance_squared: f32) -> Vector3<f32> {
-    let mut rng = rand::thread_rng();
+    let mut rng = random::rng();
     let r1 = rng.gen::<f32>();
     let r2 = rng.gen::<f32>();
-    let z = 1.0 + r2 * ((1.0 - radius.powi(2) / distance_squared).sqrt() - 1.0);
+    let z = 1.0 + r2 * ((1.0 - radius.powi(2) / distance_squared).max(0.0).sqrt() - 1.0);
     let phi = 2.0 * f32::consts::PI * r1;
     let x = phi.cos() * (1.0 - z.powi(2)).sqrt();
     let y = phi.sin() * (1.0 - z.powi(2)).sqrt();
     Vector3::new(x, y, z)
 }
 
+fn hit_sphere<'a>(center: Vector3<f32>, radius: f32, material: &'a dyn Material, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'a>> {
+    let oc = ray.origin() - center;
+    let a = ray.direction().dot(&ray.direction());
+    let b = oc.dot(&ray.direction());
+    let c = oc.dot(&oc) - radius.powi(2);
+    let discriminant = b.powi(2) - a * c;
+    if discriminant > 0.0 {
+        let sqrt_discriminant = discriminant.sqrt();
+        let t = (-b - sqrt_discriminant) / a;
+        if t < t_max && t > t_min {
+            let p = ray.point_at_parameter(t);
+            let normal = (p - center) / radius;
+            let (u, v) = get_sphere_uv(&normal);
+            return Some(HitRecord { t, u, v, p, normal, material })
+        }
+        let t = (-b + sqrt_discriminant) / a;
+        if t < t_max && t > t_min {
+            let p = ray.point_at_parameter(t);
+            let normal = (p - center) / radius;
+            let (u, v) = get_sphere_uv(&normal);
+            return Some(HitRecord { t, u, v, p, normal, material })
+        }
+    }
+    None
+}
+
+fn sphere_box(center: Vector3<f32>, radius: f32) -> AABB {
+    let radius = Vector3::new(radius, radius, radius);
+    AABB::new(center - radius, center + radius)
+}
+
+fn sphere_pdf_value(center: Vector3<f32>, radius: f32, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
+    let oc = o - center;
+    let b = oc.dot(&v);
+    let c = oc.norm_squared() - radius.powi(2);
+    // directions towards the center, the only ones sampled from inside
+    if b.powi(2) - v.norm_squared() * c > 0.0 && b < 0.0 {
+        let cos_theta_max = (1.0 - radius.powi(2) / (center - o).norm_squared()).max(0.0).sqrt();
+        let solid_angle = 2.0 * f32::consts::PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    } else {
+        0.0
+    }
+}
+
+fn sphere_random(center: Vector3<f32>, radius: f32, o: Vector3<f32>) -> Vector3<f32> {
+    let direction = center - o;
+    let distance_squared = direction.norm_squared();
+    let uvw = ONB::build_from_w(&direction);
+    uvw.local(&random_to_sphere(radius, distance_squared))
+}
+
+fn sphere_power(radius: f32, material: &dyn Material) -> f32 {
+    4.0 * f32::consts::PI * radius.powi(2) * color::luminance(&material.emission())
+}
+
 #[derive(Clone)]
 pub struct Sphere<M: Material> {
     center: Vector3<f32>,
@@ -38,53 +99,93 @@ impl<M: Material> Sphere<M> {
 }
 
 impl<M: Material> Hitable for Sphere<M> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = ray.origin() - self.center;
-        let a = ray.direction().dot(&ray.direction());
-        let b = oc.dot(&ray.direction());
-        let c = oc.dot(&oc) - self.radius.powi(2);
-        let discriminant = b.powi(2) - a * c;
-        if discriminant > 0.0 {
-            let sqrt_discriminant = discriminant.sqrt();
-            let t = (-b - sqrt_discriminant) / a;
-            if t < t_max && t > t_min {
-                let p = ray.point_at_parameter(t);
-                let normal = (p - self.center) / self.radius;
-                let (u, v) = get_sphere_uv(&normal);
-                return Some(HitRecord { t, u, v, p, normal, material: &self.material })
-            }
-            let t = (-b + sqrt_discriminant) / a;
-            if t < t_max && t > t_min {
-                let p = ray.point_at_parameter(t);
-                let normal = (p - self.center) / self.radius;
-                let (u, v) = get_sphere_uv(&normal);
-                return Some(HitRecord { t, u, v, p, normal, material: &self.material })
-            }
-        }
-        None
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center, self.radius, &self.material, ray, t_min, t_max)
     }
 
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
-        let radius = Vector3::new(self.radius, self.radius, self. radius);
-        let min = self.center - radius;
-        let max = self.center + radius;
-        Some(AABB { min, max })
+        Some(sphere_box(self.center, self.radius))
     }
 
     fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
-        if let Some(_hit) = self.hit(&Ray::new(o, v, 0.0), 0.001, f32::MAX) {
-            let cos_theta_max = (1.0 - self.radius.powi(2) / (self.center - o).norm_squared()).sqrt();
-            let solid_angle = 2.0 * f32::consts::PI * (1.0 - cos_theta_max);
-            1.0 / solid_angle
-        } else {
-            0.0
+        sphere_pdf_value(self.center, self.radius, o, v)
+    }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> {
+        sphere_random(self.center, self.radius, o)
+    }
+
+    fn power(&self) -> f32 { sphere_power(self.radius, &self.material) }
+}
+
+/// Sphere moving linearly from `center0` at `time0` to `center1` at `time1`.
+#[derive(Clone)]
+pub struct MovingSphere<M: Material> {
+    center0: Vector3<f32>,
+    center1: Vector3<f32>,
+    time0: f32,
+    time1: f32,
+    radius: f32,
+    material: M
+}
+
+impl<M: Material> MovingSphere<M> {
+    /// The center moves from `center0` at `time0` to `center1` at `time1`, which must differ.
+    pub fn new(center0: Vector3<f32>, center1: Vector3<f32>, time0: f32, time1: f32, radius: f32, material: M) -> Result<Self, String> {
+        if time0 == time1 {
+            return Err(String::from("moving sphere needs two different times"))
         }
+        Ok(MovingSphere { center0, center1, time0, time1, radius, material })
+    }
+
+    fn center(&self, time: f32) -> Vector3<f32> {
+        self.center0 + ((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
+    }
+
+    /// Light sampling has no notion of time, so it targets the sphere halfway through its motion.
+    fn sampling_center(&self) -> Vector3<f32> {
+        self.center(0.5 * (self.time0 + self.time1))
+    }
+}
+
+impl<M: Material> Hitable for MovingSphere<M> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center(ray.time()), self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        let box0 = sphere_box(self.center(t0), self.radius);
+        let box1 = sphere_box(self.center(t1), self.radius);
+        Some(aabb::surrounding_box(&box0, &box1))
+    }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
+        sphere_pdf_value(self.sampling_center(), self.radius, o, v)
     }
 
     fn random(&self, o: Vector3<f32>) -> Vector3<f32> {
-        let direction = self.center - o;
-        let distance_squared = direction.norm_squared();
-        let uvw = ONB::build_from_w(&direction);
-        uvw.local(&random_to_sphere(self.radius, distance_squared))
+        sphere_random(self.sampling_center(), self.radius, o)
+    }
+
+    fn power(&self) -> f32 { sphere_power(self.radius, &self.material) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::texture::ConstantTexture;
+
+    #[test]
+    fn samples_from_the_surface_follow_their_density() {
+        let sphere = Sphere::new(Vector3::zeros(), 1.0, Lambertian::new(ConstantTexture::new(0.5, 0.5, 0.5)));
+        for &o in &[Vector3::new(0.0, 0.0, 0.999_999), Vector3::new(0.0, 0.0, 1.000_001), Vector3::new(0.0, 0.5, 0.0)] {
+            for _ in 0..1000 {
+                let v = sphere.random(o);
+                assert!(v.iter().all(|c| c.is_finite()));
+                assert!((sphere.pdf_value(o, v) - 0.5 / f32::consts::PI).abs() < 1e-3);
+            }
+            assert_eq!(sphere.pdf_value(o, o), 0.0);
+        }
     }
 }
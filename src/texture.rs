@@ -1,7 +1,28 @@
+use std::sync::Arc;
 use nalgebra::Vector3;
+use crate::image::Image;
+use crate::perlin::Perlin;
 
-pub trait Texture: Sync {
+pub trait Texture: Send + Sync {
     fn value(&self, u: f32, v: f32, p: &Vector3<f32>) -> Vector3<f32>;
+
+    /// Approximate average color, over the surface or space it covers. By default, the mean
+    /// of the values on a grid over the unit square, in texture coordinates and in space.
+    fn average(&self) -> Vector3<f32> {
+        let n = 16;
+        let sum = (0..n * n).map(|i| {
+            let u = ((i % n) as f32 + 0.5) / n as f32;
+            let v = ((i / n) as f32 + 0.5) / n as f32;
+            self.value(u, v, &Vector3::new(u, v, 0.0))
+        }).sum::<Vector3<f32>>();
+        sum / (n * n) as f32
+    }
+}
+
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f32, v: f32, p: &Vector3<f32>) -> Vector3<f32> { (**self).value(u, v, p) }
+
+    fn average(&self) -> Vector3<f32> { (**self).average() }
 }
 
 #[derive(Clone)]
@@ -19,4 +40,201 @@ impl ConstantTexture {
 
 impl Texture for ConstantTexture {
     fn value(&self, _u: f32, _v: f32, _p: &Vector3<f32>) -> Vector3<f32> { self.color }
+
+    fn average(&self) -> Vector3<f32> { self.color }
+}
+
+#[derive(Clone, Copy)]
+pub enum Filter {
+    Nearest,
+    Bilinear
+}
+
+/// How texture coordinates out of [0, 1] are mapped back onto the image.
+#[derive(Clone, Copy)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror
+}
+
+fn wrap(i: i64, n: usize, mode: Wrap) -> usize {
+    let n = n as i64;
+    let i = match mode {
+        Wrap::Repeat => i.rem_euclid(n),
+        Wrap::Clamp => i.clamp(0, n - 1),
+        Wrap::Mirror => {
+            let i = i.rem_euclid(2 * n);
+            if i < n { i } else { 2 * n - 1 - i }
+        }
+    };
+    i as usize
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Texture from an sRGB image, with `v` going from the bottom to the top of the image.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<Vector3<f32>>,
+    filter: Filter,
+    wrap: Wrap
+}
+
+impl ImageTexture {
+    pub fn new(image: &Image, filter: Filter, wrap: Wrap) -> Self {
+        let table = (0..=255).map(srgb_to_linear).collect::<Vec<f32>>();
+        let (width, height) = (image.width(), image.height());
+        let texels = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let [r, g, b] = image.pixel(x, y);
+                Vector3::new(table[r as usize], table[g as usize], table[b as usize])
+            }).collect();
+        ImageTexture { width, height, texels, filter, wrap }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vector3<f32> {
+        self.texels[wrap(y, self.height, self.wrap) * self.width + wrap(x, self.width, self.wrap)]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _p: &Vector3<f32>) -> Vector3<f32> {
+        let x = u * self.width as f32;
+        let y = (1.0 - v) * self.height as f32;
+        match self.filter {
+            Filter::Nearest => self.texel(x.floor() as i64, y.floor() as i64),
+            Filter::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                (1.0 - fy) * ((1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0))
+                    + fy * ((1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1))
+            }
+        }
+    }
+
+    fn average(&self) -> Vector3<f32> {
+        self.texels.iter().sum::<Vector3<f32>>() / self.texels.len().max(1) as f32
+    }
+}
+
+/// Checker board in space, alternating cubes of side `size`.
+#[derive(Clone)]
+pub struct CheckerTexture<T: Texture, U: Texture> {
+    odd: T,
+    even: U,
+    size: f32
+}
+
+impl<T: Texture, U: Texture> CheckerTexture<T, U> {
+    pub fn new(size: f32, odd: T, even: U) -> Self { CheckerTexture { odd, even, size } }
+}
+
+impl<T: Texture, U: Texture> Texture for CheckerTexture<T, U> {
+    fn value(&self, u: f32, v: f32, p: &Vector3<f32>) -> Vector3<f32> {
+        let cell = p.map(|c| (c / self.size).floor() as i64);
+        if (cell.x + cell.y + cell.z).rem_euclid(2) == 1 {
+            self.odd.value(u, v, p)
+        } else {
+            self.even.value(u, v, p)
+        }
+    }
+
+    fn average(&self) -> Vector3<f32> { 0.5 * (self.odd.average() + self.even.average()) }
+}
+
+/// Checker board in texture space, with `nu` by `nv` squares over the [0, 1] range.
+#[derive(Clone)]
+pub struct UvCheckerTexture<T: Texture, U: Texture> {
+    odd: T,
+    even: U,
+    nu: f32,
+    nv: f32
+}
+
+impl<T: Texture, U: Texture> UvCheckerTexture<T, U> {
+    pub fn new(nu: f32, nv: f32, odd: T, even: U) -> Self { UvCheckerTexture { odd, even, nu, nv } }
+}
+
+impl<T: Texture, U: Texture> Texture for UvCheckerTexture<T, U> {
+    fn value(&self, u: f32, v: f32, p: &Vector3<f32>) -> Vector3<f32> {
+        let cell = (u * self.nu).floor() as i64 + (v * self.nv).floor() as i64;
+        if cell.rem_euclid(2) == 1 {
+            self.odd.value(u, v, p)
+        } else {
+            self.even.value(u, v, p)
+        }
+    }
+
+    fn average(&self) -> Vector3<f32> { 0.5 * (self.odd.average() + self.even.average()) }
+}
+
+/// Gray turbulence.
+#[derive(Clone)]
+pub struct NoiseTexture {
+    noise: Perlin,
+    scale: f32
+}
+
+impl NoiseTexture {
+    pub fn new(noise: Perlin, scale: f32) -> Self { NoiseTexture { noise, scale } }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, p: &Vector3<f32>) -> Vector3<f32> {
+        Vector3::repeat(self.noise.turb(&(self.scale * p), 7).min(1.0))
+    }
+}
+
+/// Veins along the z axis, made irregular by turbulence.
+#[derive(Clone)]
+pub struct MarbleTexture {
+    noise: Perlin,
+    scale: f32,
+    color: Vector3<f32>
+}
+
+impl MarbleTexture {
+    pub fn new(noise: Perlin, scale: f32, color: Vector3<f32>) -> Self { MarbleTexture { noise, scale, color } }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f32, _v: f32, p: &Vector3<f32>) -> Vector3<f32> {
+        self.color * 0.5 * (1.0 + (self.scale * p.z + 10.0 * self.noise.turb(p, 7)).sin())
+    }
+
+    fn average(&self) -> Vector3<f32> { 0.5 * self.color }
+}
+
+/// Growth rings around the y axis, `scale` rings per unit, perturbed by noise.
+#[derive(Clone)]
+pub struct WoodTexture {
+    noise: Perlin,
+    scale: f32,
+    light: Vector3<f32>,
+    dark: Vector3<f32>
+}
+
+impl WoodTexture {
+    pub fn new(noise: Perlin, scale: f32, light: Vector3<f32>, dark: Vector3<f32>) -> Self {
+        WoodTexture { noise, scale, light, dark }
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: f32, _v: f32, p: &Vector3<f32>) -> Vector3<f32> {
+        let distance = (p.x.powi(2) + p.z.powi(2)).sqrt() * self.scale;
+        let rings = distance + 2.0 * self.noise.noise(&(p * self.scale));
+        let t = (rings - rings.floor()).powi(3);
+        self.light * (1.0 - t) + self.dark * t
+    }
+
+    /// Cubes of a uniform fraction average a quarter.
+    fn average(&self) -> Vector3<f32> { 0.75 * self.light + 0.25 * self.dark }
 }
@@ -0,0 +1,80 @@
+use std::str::FromStr;
+use nalgebra::Vector3;
+use crate::color::luminance;
+
+#[derive(Clone, Copy)]
+pub enum Operator {
+    Clamp,
+    Reinhard,
+    ExtendedReinhard,
+    Aces
+}
+
+impl FromStr for Operator {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(Operator::Clamp),
+            "reinhard" => Ok(Operator::Reinhard),
+            "extended-reinhard" => Ok(Operator::ExtendedReinhard),
+            "aces" => Ok(Operator::Aces),
+            _ => Err(())
+        }
+    }
+}
+
+/// Scales the color so that its luminance becomes `f(luminance)`, preserving hue.
+fn map_luminance(c: Vector3<f32>, f: impl Fn(f32) -> f32) -> Vector3<f32> {
+    let l = luminance(&c);
+    if l > 0.0 { c * (f(l) / l) } else { Vector3::zeros() }
+}
+
+/// Krzysztof Narkowicz's fit of the ACES filmic curve, with its 0.6 input scale.
+fn aces(x: f32) -> f32 {
+    let x = 0.6 * x;
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+/// sRGB opto-electronic transfer function, from linear to encoded values.
+pub fn srgb_oetf(x: f32) -> f32 {
+    if x <= 0.003_130_8 { 12.92 * x } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+#[derive(Clone, Copy)]
+pub struct ToneMapper {
+    pub operator: Operator,
+    /// Exposure adjustment in stops, applied before the operator.
+    pub exposure: f32,
+    /// Radiance mapped to white by the extended Reinhard operator.
+    pub white: f32
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        ToneMapper { operator: Operator::Clamp, exposure: 0.0, white: 1.0 }
+    }
+}
+
+impl ToneMapper {
+    /// Maps linear scene radiance to display linear values in [0, 1].
+    pub fn map(&self, c: &Vector3<f32>) -> Vector3<f32> {
+        let c = c.map(|c| c.max(0.0)) * 2f32.powf(self.exposure);
+        let mapped = match self.operator {
+            Operator::Clamp => c,
+            Operator::Reinhard => map_luminance(c, |l| l / (1.0 + l)),
+            Operator::ExtendedReinhard => {
+                let white_squared = self.white.powi(2);
+                map_luminance(c, |l| l * (1.0 + l / white_squared) / (1.0 + l))
+            },
+            Operator::Aces => c.map(aces)
+        };
+        mapped.map(|c| c.clamp(0.0, 1.0))
+    }
+
+    /// Tone maps and encodes a pixel as 8 bit sRGB.
+    pub fn encode(&self, c: &Vector3<f32>) -> [u8; 3] {
+        let c = self.map(c).map(|c| (255.0 * srgb_oetf(c) + 0.5) as u8);
+        [c.x, c.y, c.z]
+    }
+}
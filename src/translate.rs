@@ -13,7 +13,7 @@ impl<H: Hitable> Translate<H> {
 }
 
 impl<H: Hitable> Hitable for Translate<H> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let moved_ray = Ray::new(ray.origin() - self.offset, ray.direction(), ray.time());
         self.hitable.hit(&moved_ray, t_min, t_max).map(|mut hit| {
             hit.p += self.offset;
@@ -28,4 +28,10 @@ impl<H: Hitable> Hitable for Translate<H> {
             b
         })
     }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 { self.hitable.pdf_value(o - self.offset, v) }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> { self.hitable.random(o - self.offset) }
+
+    fn power(&self) -> f32 { self.hitable.power() }
 }
@@ -0,0 +1,296 @@
+use std::f32;
+use std::sync::Arc;
+use nalgebra::{Vector2, Vector3};
+use rand::Rng;
+use crate::ray::Ray;
+use crate::random;
+use crate::hitable::{Hitable, HitableList, HitRecord};
+use crate::material::Material;
+use crate::aabb::AABB;
+use crate::bvh::BvhNode;
+use crate::color;
+
+const EPSILON: f32 = 1e-8;
+
+/// Möller–Trumbore intersection, returns the distance and the barycentric coordinates of `p1` and `p2`.
+fn intersect(p: [&Vector3<f32>; 3], ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+    let edge1 = p[1] - p[0];
+    let edge2 = p[2] - p[0];
+    let pvec = ray.direction().cross(&edge2);
+    let det = edge1.dot(&pvec);
+    if det.abs() < EPSILON {
+        return None
+    }
+    let inv_det = 1.0 / det;
+    let tvec = ray.origin() - p[0];
+    let b1 = tvec.dot(&pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None
+    }
+    let qvec = tvec.cross(&edge1);
+    let b2 = ray.direction().dot(&qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None
+    }
+    let t = edge2.dot(&qvec) * inv_det;
+    if t < t_min || t > t_max {
+        None
+    } else {
+        Some((t, b1, b2))
+    }
+}
+
+/// Interpolates normals and texture coordinates at the given barycentric coordinates.
+/// Without normals the face normal is used, following the counterclockwise winding;
+/// without texture coordinates `u` and `v` are the barycentric coordinates themselves.
+fn shade(
+    p: [&Vector3<f32>; 3], normals: Option<[&Vector3<f32>; 3]>, uvs: Option<[&Vector2<f32>; 3]>,
+    b1: f32, b2: f32) -> (Vector3<f32>, f32, f32) {
+    let b0 = 1.0 - b1 - b2;
+    let face_normal = (p[1] - p[0]).cross(&(p[2] - p[0])).normalize();
+    let normal = match normals {
+        // opposite vertex normals can cancel out, leaving only the face to go by
+        Some(n) => match (b0 * n[0] + b1 * n[1] + b2 * n[2]).try_normalize(0.0) {
+            Some(normal) if normal.dot(&face_normal) < 0.0 => -normal,
+            Some(normal) => normal,
+            None => face_normal
+        },
+        None => face_normal
+    };
+    let (u, v) = match uvs {
+        Some(uv) => {
+            let uv = b0 * uv[0] + b1 * uv[1] + b2 * uv[2];
+            (uv.x, uv.y)
+        },
+        None => (b1, b2)
+    };
+    (normal, u, v)
+}
+
+fn area(p: [&Vector3<f32>; 3]) -> f32 {
+    0.5 * (p[1] - p[0]).cross(&(p[2] - p[0])).norm()
+}
+
+fn bounding_box(p: [&Vector3<f32>; 3]) -> AABB {
+    let padding = Vector3::new(0.0001, 0.0001, 0.0001);
+    let min = p[0].inf(p[1]).inf(p[2]) - padding;
+    let max = p[0].sup(p[1]).sup(p[2]) + padding;
+    AABB::new(min, max)
+}
+
+/// Uniformly distributed point on the triangle.
+fn random_point(p: [&Vector3<f32>; 3]) -> Vector3<f32> {
+    let mut rng = random::rng();
+    let su = rng.gen::<f32>().sqrt();
+    let b1 = rng.gen::<f32>() * su;
+    let b0 = 1.0 - su;
+    b0 * p[0] + b1 * p[1] + (1.0 - b0 - b1) * p[2]
+}
+
+/// Solid angle density of sampling a point uniformly over the triangle, seen from `o` along `v`,
+/// which hits it at distance `t`. The face normal gives the foreshortening of the area,
+/// shading normals bending only the light.
+fn area_pdf_value(p: [&Vector3<f32>; 3], t: f32, v: Vector3<f32>) -> f32 {
+    let distance_squared = t.powi(2) * v.norm_squared();
+    let face_normal = (p[1] - p[0]).cross(&(p[2] - p[0])).normalize();
+    let cosine = v.dot(&face_normal).abs() / v.norm();
+    if cosine != 0.0 { distance_squared / (cosine * area(p)) } else { 0.0 }
+}
+
+pub struct Triangle<M: Material> {
+    vertices: [Vector3<f32>; 3],
+    normals: Option<[Vector3<f32>; 3]>,
+    uvs: Option<[Vector2<f32>; 3]>,
+    material: M
+}
+
+impl<M: Material> Triangle<M> {
+    pub fn new(
+        vertices: [Vector3<f32>; 3], normals: Option<[Vector3<f32>; 3]>, uvs: Option<[Vector2<f32>; 3]>,
+        material: M) -> Self {
+        Triangle { vertices, normals, uvs, material }
+    }
+
+    fn points(&self) -> [&Vector3<f32>; 3] { [&self.vertices[0], &self.vertices[1], &self.vertices[2]] }
+}
+
+impl<M: Material> Hitable for Triangle<M> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        intersect(self.points(), ray, t_min, t_max).map(|(t, b1, b2)| {
+            let normals = self.normals.as_ref().map(|n| [&n[0], &n[1], &n[2]]);
+            let uvs = self.uvs.as_ref().map(|uv| [&uv[0], &uv[1], &uv[2]]);
+            let (normal, u, v) = shade(self.points(), normals, uvs, b1, b2);
+            HitRecord { t, u, v, p: ray.point_at_parameter(t), normal, material: &self.material }
+        })
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+        Some(bounding_box(self.points()))
+    }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
+        match intersect(self.points(), &Ray::new(o, v, 0.0), 0.001, f32::MAX) {
+            Some((t, _, _)) => area_pdf_value(self.points(), t, v),
+            None => 0.0
+        }
+    }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> {
+        random_point(self.points()) - o
+    }
+
+    fn power(&self) -> f32 { area(self.points()) * color::luminance(&self.material.emission()) }
+}
+
+/// Corner of a mesh face, as indices in the vertex buffers of the mesh.
+#[derive(Clone, Copy)]
+pub struct Vertex {
+    pub position: usize,
+    pub normal: Option<usize>,
+    pub uv: Option<usize>
+}
+
+/// Vertex attributes, which can be shared by several meshes.
+#[derive(Default)]
+pub struct VertexBuffers {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    pub uvs: Vec<Vector2<f32>>
+}
+
+struct MeshData<M: Material> {
+    buffers: Arc<VertexBuffers>,
+    material: M
+}
+
+struct MeshTriangle<M: Material> {
+    mesh: Arc<MeshData<M>>,
+    face: [Vertex; 3]
+}
+
+impl<M: Material> MeshTriangle<M> {
+    fn points(&self) -> [&Vector3<f32>; 3] {
+        let positions = &self.mesh.buffers.positions;
+        [&positions[self.face[0].position], &positions[self.face[1].position], &positions[self.face[2].position]]
+    }
+}
+
+impl<M: Material> Hitable for MeshTriangle<M> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        intersect(self.points(), ray, t_min, t_max).map(|(t, b1, b2)| {
+            let [a, b, c] = self.face;
+            let buffers = &self.mesh.buffers;
+            let normals = a.normal.and_then(|a| Some([a, b.normal?, c.normal?]))
+                .map(|n| [&buffers.normals[n[0]], &buffers.normals[n[1]], &buffers.normals[n[2]]]);
+            let uvs = a.uv.and_then(|a| Some([a, b.uv?, c.uv?]))
+                .map(|uv| [&buffers.uvs[uv[0]], &buffers.uvs[uv[1]], &buffers.uvs[uv[2]]]);
+            let (normal, u, v) = shade(self.points(), normals, uvs, b1, b2);
+            HitRecord { t, u, v, p: ray.point_at_parameter(t), normal, material: &self.mesh.material }
+        })
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+        Some(bounding_box(self.points()))
+    }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 {
+        match intersect(self.points(), &Ray::new(o, v, 0.0), 0.001, f32::MAX) {
+            Some((t, _, _)) => area_pdf_value(self.points(), t, v),
+            None => 0.0
+        }
+    }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> {
+        random_point(self.points()) - o
+    }
+
+    fn power(&self) -> f32 { area(self.points()) * color::luminance(&self.mesh.material.emission()) }
+}
+
+/// Indexed triangle mesh, whose faces share the vertex buffers and a single material.
+/// Faces are kept in a bvh, which samples them in proportion to their area when the mesh is a light.
+pub struct TriangleMesh {
+    triangles: BvhNode,
+    bbox: AABB
+}
+
+impl TriangleMesh {
+    /// Faces with an index out of the vertex buffers are an error.
+    pub fn new<M: Material + 'static>(buffers: Arc<VertexBuffers>, faces: Vec<[Vertex; 3]>,
+                                      material: M) -> Result<Self, String> {
+        for vertex in faces.iter().flatten() {
+            if vertex.position >= buffers.positions.len()
+                || vertex.normal.is_some_and(|n| n >= buffers.normals.len())
+                || vertex.uv.is_some_and(|uv| uv >= buffers.uvs.len()) {
+                return Err(String::from("vertex index out of range"))
+            }
+        }
+        if faces.is_empty() {
+            return Err(String::from("mesh has no faces"))
+        }
+        let mesh = Arc::new(MeshData { buffers, material });
+        let mut triangles = HitableList::default();
+        for face in faces {
+            triangles.push(MeshTriangle { mesh: mesh.clone(), face });
+        }
+        let triangles = BvhNode::new(triangles, 0.0, 1.0);
+        let bbox = triangles.bounding_box(0.0, 1.0).unwrap();
+        Ok(TriangleMesh { triangles, bbox })
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.triangles.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> { Some(self.bbox) }
+
+    fn pdf_value(&self, o: Vector3<f32>, v: Vector3<f32>) -> f32 { self.triangles.pdf_value(o, v) }
+
+    fn random(&self, o: Vector3<f32>) -> Vector3<f32> { self.triangles.random(o) }
+
+    fn power(&self) -> f32 { self.triangles.power() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::texture::ConstantTexture;
+
+    /// Integral of the density of `hitable` seen from `o`, over directions evenly spread on the sphere.
+    fn integrate_pdf(hitable: &dyn Hitable, o: Vector3<f32>) -> f32 {
+        let n = 200_000;
+        let golden_angle = f32::consts::PI * (3.0 - 5f32.sqrt());
+        (0..n).map(|i| {
+            let z = 1.0 - (2 * i + 1) as f32 / n as f32;
+            let r = (1.0 - z * z).sqrt();
+            let phi = golden_angle * i as f32;
+            hitable.pdf_value(o, Vector3::new(r * phi.cos(), r * phi.sin(), z))
+        }).sum::<f32>() * 4.0 * f32::consts::PI / n as f32
+    }
+
+    #[test]
+    fn mesh_density_integrates_to_one() {
+        // two stacked faces crossed by the same directions, and a tilted one with a bent normal
+        let positions = vec![
+            Vector3::new(-1.0, -1.0, 1.0), Vector3::new(1.0, -1.0, 1.0), Vector3::new(0.0, 1.0, 1.0),
+            Vector3::new(-1.0, -1.0, 2.0), Vector3::new(1.0, -1.0, 2.0), Vector3::new(0.0, 1.0, 2.0),
+            Vector3::new(2.0, 0.0, 0.0), Vector3::new(2.0, 1.0, 1.0), Vector3::new(3.0, 0.0, 1.0)
+        ];
+        let normals = vec![Vector3::new(1.0, 0.0, 1.0).normalize()];
+        let vertex = |position, normal| Vertex { position, normal, uv: None };
+        let faces = vec![
+            [vertex(0, None), vertex(1, None), vertex(2, None)],
+            [vertex(3, None), vertex(4, None), vertex(5, None)],
+            [vertex(6, Some(0)), vertex(7, Some(0)), vertex(8, Some(0))]
+        ];
+        let buffers = Arc::new(VertexBuffers { positions, normals, uvs: Vec::new() });
+        let mesh = TriangleMesh::new(buffers, faces, Lambertian::new(ConstantTexture::new(1.0, 1.0, 1.0))).unwrap();
+        assert!((integrate_pdf(&mesh, Vector3::zeros()) - 1.0).abs() < 0.02);
+        for _ in 0..100 {
+            assert!(mesh.pdf_value(Vector3::zeros(), mesh.random(Vector3::zeros())) > 0.0);
+        }
+    }
+}
@@ -0,0 +1,437 @@
+const WINDOW_SIZE: usize = 32768;
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 128;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u32
+}
+
+impl BitWriter {
+    fn new() -> Self { BitWriter { out: Vec::new(), buffer: 0, count: 0 } }
+
+    /// Writes the `n` least significant bits of `value`, least significant first.
+    fn write_bits(&mut self, value: u32, n: u32) {
+        self.buffer |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting from their most significant bit.
+    fn write_code(&mut self, code: u32, n: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - n), n)
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+fn write_literal(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8)
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+    write_literal(writer, 257 + code as u32);
+    writer.write_bits((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+    let code = DIST_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+    writer.write_code(code as u32, 5);
+    writer.write_bits((distance - DIST_BASE[code] as usize) as u32, DIST_EXTRA[code] as u32);
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    let h = (data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize;
+    h & (HASH_SIZE - 1)
+}
+
+fn insert(data: &[u8], i: usize, head: &mut [usize], prev: &mut [usize]) {
+    if i + MIN_MATCH <= data.len() {
+        let h = hash(data, i);
+        prev[i % WINDOW_SIZE] = head[h];
+        head[h] = i;
+    }
+}
+
+/// Compresses `data` into a zlib stream: a single deflate block with the fixed
+/// Huffman codes, fed by a hash chain LZ77 matcher.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    writer.write_bits(0x78, 8);
+    writer.write_bits(0x01, 8);
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        if i + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(data, i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..].iter().zip(&data[i..i + max_length])
+                    .take_while(|(a, b)| a == b).count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = i - candidate;
+                    if length == max_length {
+                        break
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+        if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_distance);
+            for j in i..i + best_length {
+                insert(data, j, &mut head, &mut prev);
+            }
+            i += best_length;
+        } else {
+            write_literal(&mut writer, data[i] as u32);
+            insert(data, i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    write_literal(&mut writer, 256);
+    let mut out = writer.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self { BitReader { data, pos: 0, buffer: 0, count: 0 } }
+
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or("unexpected end of compressed data")?;
+            self.buffer |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << n) - 1) as u32;
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code, as the number of codes of each length and the symbols sorted by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for i in 1..16 {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    /// Reads a code one bit at a time, most significant first.
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize])
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(String::from("invalid huffman code"))
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let hlit = reader.bits(5)? as usize + 257;
+    let hdist = reader.bits(5)? as usize + 1;
+    let hclen = reader.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &i in ORDER.iter().take(hclen) {
+        code_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let (length, repeat) = match code_lengths.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or("repeated length without a previous one")?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?)
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths.len() > hlit + hdist {
+        return Err(String::from("too many code lengths"))
+    }
+    Ok((Huffman::new(&lengths[..hlit]), Huffman::new(&lengths[hlit..])))
+}
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let code = symbol - 257;
+                let length = LENGTH_BASE[code] as usize + reader.bits(LENGTH_EXTRA[code] as u32)? as usize;
+                let code = distances.decode(reader)? as usize;
+                if code >= DIST_BASE.len() {
+                    return Err(String::from("invalid distance code"))
+                }
+                let distance = DIST_BASE[code] as usize + reader.bits(DIST_EXTRA[code] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(String::from("distance too far back"))
+                }
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            },
+            _ => return Err(String::from("invalid literal/length code"))
+        }
+    }
+}
+
+/// Decompresses a zlib stream, checking its adler32 checksum.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 6 || data[0] & 0x0f != 8 || !((data[0] as u16) << 8 | data[1] as u16).is_multiple_of(31) {
+        return Err(String::from("invalid zlib header"))
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(String::from("preset dictionaries are not supported"))
+    }
+    let mut reader = BitReader::new(&data[2..]);
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let pos = reader.pos;
+                let header = reader.data.get(pos..pos + 4).ok_or("unexpected end of compressed data")?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                if len != !u16::from_le_bytes([header[2], header[3]]) as usize {
+                    return Err(String::from("invalid stored block length"))
+                }
+                let block = reader.data.get(pos + 4..pos + 4 + len).ok_or("unexpected end of compressed data")?;
+                out.extend_from_slice(block);
+                reader.pos = pos + 4 + len;
+            },
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            },
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            },
+            _ => return Err(String::from("invalid block type"))
+        }
+        if last {
+            break
+        }
+    }
+    let pos = 2 + reader.pos;
+    let checksum = data.get(pos..pos + 4).ok_or("missing adler32 checksum")?;
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&out) {
+        return Err(String::from("adler32 checksum mismatch"))
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes from a linear congruential generator, which deflate can hardly match.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 12345u32;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    /// Zlib stream of `blocks` stored uncompressed, the last one marked final.
+    fn stored(blocks: &[&[u8]]) -> Vec<u8> {
+        let mut out = vec![0x78, 0x01];
+        for (i, block) in blocks.iter().enumerate() {
+            out.push((i + 1 == blocks.len()) as u8);
+            out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+            out.extend_from_slice(block);
+        }
+        out.extend_from_slice(&adler32(&blocks.concat()).to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        // longer than the 5552 bytes between reductions modulo 65521
+        let long = vec![0xff; 100_000];
+        let (a, b) = long.iter().fold((1u64, 0u64), |(a, b), &x| ((a + x as u64) % 65521, (b + a + x as u64) % 65521));
+        assert_eq!(adler32(&long), ((b << 16) | a) as u32);
+    }
+
+    #[test]
+    fn known_stream() {
+        // inflated back by python's zlib.decompress
+        assert_eq!(compress(b"abcabcabcabd abcabcabcabd"),
+                   [0x78, 0x01, 0x4b, 0x4c, 0x4a, 0x86, 0xa1, 0x14, 0x05, 0x64, 0x0e, 0x00, 0x79, 0x28, 0x09, 0x53]);
+    }
+
+    #[test]
+    fn stream_layout() {
+        let repeated = b"abcabcabcabd".repeat(100);
+        for data in [&b""[..], b"a", b"hello, world", &repeated, &vec![7u8; 1000]] {
+            let compressed = compress(data);
+            assert_eq!(u16::from_be_bytes([compressed[0], compressed[1]]) % 31, 0, "header check bits");
+            assert_eq!(compressed[0] & 0x0f, 8, "deflate method");
+            assert_eq!(compressed[2] & 7, 3, "single final fixed huffman block");
+            assert_eq!(compressed[compressed.len() - 4..], adler32(data).to_be_bytes());
+        }
+        assert!(compress(&repeated).len() < repeated.len() / 10);
+    }
+
+    #[test]
+    fn fixed_round_trip() {
+        let repeated = b"abcabcabcabd".repeat(100);
+        let long_run = vec![7u8; 1000];
+        // longer than the window, so matches have to stay within it
+        let mut far = noise(40000);
+        far.extend_from_within(..1000);
+        for data in [&b""[..], b"a", b"hello, world", &repeated, &long_run, &noise(5000), &far] {
+            let compressed = compress(data);
+            assert_eq!((compressed[2] >> 1) & 3, 1, "fixed huffman block");
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn stored_blocks() {
+        let data = noise(300);
+        let (first, second) = data.split_at(100);
+        assert_eq!(decompress(&stored(&[first, second])).unwrap(), data);
+        assert_eq!(decompress(&stored(&[b""])).unwrap(), b"");
+    }
+
+    #[test]
+    fn dynamic_block() {
+        // zlib.compress at level 9, which picks dynamic huffman codes
+        let compressed = [
+            0x78, 0xda, 0xb5, 0xcb, 0xc7, 0x0d, 0x80, 0x30, 0x10, 0x44, 0xd1, 0x56, 0xa6, 0x00, 0x44, 0x4f,
+            0x36, 0xac, 0x03, 0xc1, 0xeb, 0x6c, 0xa0, 0x7a, 0x56, 0xf4, 0xc0, 0x71, 0xf4, 0xdf, 0x54, 0x47,
+            0x48, 0xcd, 0x2f, 0x3b, 0x74, 0xe6, 0x11, 0x60, 0xf8, 0xc2, 0xd6, 0xce, 0x58, 0xc0, 0x9d, 0x32,
+            0xaa, 0xe4, 0x43, 0x3d, 0x37, 0x56, 0xb6, 0xd3, 0xb7, 0xfe, 0xc1, 0x51, 0x89, 0x3b, 0x6f, 0x68,
+            0x41, 0xc3, 0x57, 0x07, 0xe3, 0x3b, 0x49, 0x7a, 0x28, 0xe0, 0xf0, 0xa9, 0x71, 0x96, 0xaf, 0x2d,
+            0xf3, 0x0b, 0xe6, 0xb1, 0x3f, 0x87
+        ];
+        assert_eq!((compressed[2] >> 1) & 3, 2, "dynamic huffman block");
+        let mut data = b"the quick brown fox jumps over the lazy dog, ".repeat(3);
+        data.extend_from_slice(b"pack my box with five dozen liquor jugs.");
+        assert_eq!(decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn corrupted_stream() {
+        let mut compressed = compress(b"hello, world");
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(decompress(&compressed).is_err());
+        assert!(decompress(&compressed[..4]).is_err());
+    }
+}
\ No newline at end of file